@@ -0,0 +1,527 @@
+//! `#[derive(Deser, Ser)]` for records of `avirs`.
+//!
+//! Fields are read and written in declaration order, one at a time, so the
+//! in-memory layout and byte order of the target never matter. Supported attributes:
+//!
+//! * `#[deser(big)]`, `#[deser(little)]` on struct or field: fixed byte order of integers
+//!   and floats. Without them the order passed to `deser_endian`/`ser_endian` is used,
+//!   little endian for plain `deser`/`ser`.
+//! * `#[deser(prefix = "u16")]` on `Vec` or `String`: element count stored before data.
+//! * `#[deser(len = "expr")]` on `Vec` or `String`: element count computed from earlier fields.
+//!   Writing fails if the length differs from it.
+//! * `#[deser(fixed = 20)]` on `String`: NUL padded text of fixed size.
+//! * `#[deser(rest)]` on `Vec` or `String`: everything up to end of chunk.
+//! * `#[deser(crate = "path")]` on struct: path of module holding `Deser` and `Ser`,
+//!   `::deser` by default, which is right inside `avirs` only. Use `::avirs::deser` elsewhere.
+//!
+//! Other field types are read with their own `Deser` impl and written with `Ser`.
+//! Counts come from the data, so items are pushed as they are read instead of
+//! being allocated up front.
+//!
+//! Integers are read and written through `::byteorder`, so the crate deriving
+//! has to link `byteorder` under that name.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as Tokens};
+use syn::{Data, DeriveInput, Fields, Lit, Meta, NestedMeta, Type, Ident, Attribute, Error};
+
+#[derive(Clone, Copy)]
+enum Endian {
+	Little,
+	Big,
+	/// Order passed to `deser_endian`/`ser_endian`.
+	Runtime,
+}
+
+enum Count {
+	/// Count read as integer of given type.
+	Prefix(Ident),
+	/// Count computed from expression.
+	Len(syn::Expr),
+	/// Read until end of data.
+	Rest,
+}
+
+struct FieldAttrs {
+	endian: Option<Endian>,
+	count: Option<Count>,
+	fixed: Option<usize>,
+	/// Path of `deser` module, struct only.
+	krate: Option<syn::Path>,
+}
+
+fn parse_attrs(attrs: &[Attribute]) -> syn::Result<FieldAttrs> {
+	let mut result = FieldAttrs {
+		endian: None,
+		count: None,
+		fixed: None,
+		krate: None,
+	};
+	for attr in attrs {
+		if !attr.path.is_ident("deser") {
+			continue;
+		}
+		let list = match attr.parse_meta()? {
+			Meta::List(list) => list,
+			meta => return Err(Error::new_spanned(meta, "expected #[deser(...)]")),
+		};
+		for nested in list.nested {
+			match nested {
+				NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("little") => result.endian = Some(Endian::Little),
+				NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("big") => result.endian = Some(Endian::Big),
+				NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("rest") => result.count = Some(Count::Rest),
+				NestedMeta::Meta(Meta::NameValue(ref value)) if value.path.is_ident("prefix") => match value.lit {
+					Lit::Str(ref text) => result.count = Some(Count::Prefix(text.parse()?)),
+					ref lit => return Err(Error::new_spanned(lit, "expected integer type name")),
+				},
+				NestedMeta::Meta(Meta::NameValue(ref value)) if value.path.is_ident("len") => match value.lit {
+					Lit::Str(ref text) => result.count = Some(Count::Len(text.parse()?)),
+					ref lit => return Err(Error::new_spanned(lit, "expected expression")),
+				},
+				NestedMeta::Meta(Meta::NameValue(ref value)) if value.path.is_ident("fixed") => match value.lit {
+					Lit::Int(ref size) => result.fixed = Some(size.base10_parse()?),
+					ref lit => return Err(Error::new_spanned(lit, "expected size in bytes")),
+				},
+				NestedMeta::Meta(Meta::NameValue(ref value)) if value.path.is_ident("crate") => match value.lit {
+					Lit::Str(ref text) => result.krate = Some(text.parse()?),
+					ref lit => return Err(Error::new_spanned(lit, "expected path")),
+				},
+				nested => return Err(Error::new_spanned(nested, "unknown deser attribute")),
+			}
+		}
+	}
+	Ok(result)
+}
+
+/// Name of primitive type, if `ty` is one.
+fn primitive(ty: &Type) -> Option<String> {
+	match *ty {
+		Type::Path(ref path) if path.qself.is_none() => {
+			let name = path.path.get_ident()?.to_string();
+			match &name[..] {
+				"u8" | "i8" | "u16" | "i16" | "u32" | "i32" | "u64" | "i64" | "f32" | "f64" => Some(name),
+				_ => None,
+			}
+		}
+		_ => None,
+	}
+}
+
+/// Element type of `Vec<T>`.
+fn vec_element(ty: &Type) -> Option<&Type> {
+	let path = match *ty {
+		Type::Path(ref path) if path.qself.is_none() => path,
+		_ => return None,
+	};
+	let segment = path.path.segments.last()?;
+	if segment.ident != "Vec" {
+		return None;
+	}
+	match segment.arguments {
+		syn::PathArguments::AngleBracketed(ref args) if args.args.len() == 1 => match args.args[0] {
+			syn::GenericArgument::Type(ref ty) => Some(ty),
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+fn is_string(ty: &Type) -> bool {
+	match *ty {
+		Type::Path(ref path) => path.path.is_ident("String"),
+		_ => false,
+	}
+}
+
+fn is_u8(ty: &Type) -> bool {
+	primitive(ty).as_ref().map(|name| &name[..]) == Some("u8")
+}
+
+/// Expression of `Endian` value passed to nested records.
+fn endian_value(endian: Endian, krate: &Tokens) -> Tokens {
+	match endian {
+		Endian::Little => quote!(#krate::Endian::Little),
+		Endian::Big => quote!(#krate::Endian::Big),
+		Endian::Runtime => quote!(__endian),
+	}
+}
+
+/// `call` with `::byteorder` order type `order` in `endian`, picked at runtime if needed.
+fn with_order<F: Fn(Tokens) -> Tokens>(endian: Endian, krate: &Tokens, call: F) -> Tokens {
+	match endian {
+		Endian::Little => call(quote!(::byteorder::LittleEndian)),
+		Endian::Big => call(quote!(::byteorder::BigEndian)),
+		Endian::Runtime => {
+			let little = call(quote!(::byteorder::LittleEndian));
+			let big = call(quote!(::byteorder::BigEndian));
+			quote!(match __endian {
+				#krate::Endian::Little => #little,
+				#krate::Endian::Big => #big,
+			})
+		}
+	}
+}
+
+/// Expression reading single value of `ty`.
+fn read_value(ty: &Type, endian: Endian, krate: &Tokens) -> Tokens {
+	if let Some(name) = primitive(ty) {
+		let method = Ident::new(&format!("read_{}", name), Span::call_site());
+		if name == "u8" || name == "i8" {
+			return quote!(::byteorder::ReadBytesExt::#method(read)?);
+		}
+		let read = with_order(endian, krate, |order| quote!(::byteorder::ReadBytesExt::#method::<#order>(read)));
+		return quote!(#read?);
+	}
+	if let Type::Array(ref array) = *ty {
+		let len = &array.len;
+		if is_u8(&array.elem) {
+			return quote!({
+				let mut buf = [0u8; #len];
+				::std::io::Read::read_exact(read, &mut buf)?;
+				buf
+			});
+		}
+		let elem = &array.elem;
+		let item = read_value(elem, endian, krate);
+		return quote!({
+			let mut buf: [#elem; #len] = [::std::default::Default::default(); #len];
+			for item in buf.iter_mut() {
+				*item = #item;
+			}
+			buf
+		});
+	}
+	let endian = endian_value(endian, krate);
+	quote!(#krate::Deser::deser_endian(read, #endian)?)
+}
+
+fn text(bytes: Tokens) -> Tokens {
+	quote!({
+		let bytes = #bytes;
+		let end = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+		::std::string::String::from_utf8_lossy(&bytes[..end]).into_owned()
+	})
+}
+
+/// Expression reading `count` bytes. Short data is an error, as with `read_exact`.
+fn read_bytes(count: Tokens) -> Tokens {
+	quote!({
+		let count = #count;
+		let mut bytes = ::std::vec::Vec::new();
+		::std::io::Read::read_to_end(&mut ::std::io::Read::take(&mut *read, count as u64), &mut bytes)?;
+		if bytes.len() < count {
+			return Err(::std::io::Error::new(::std::io::ErrorKind::UnexpectedEof, format!("Expected {} bytes, found {}", count, bytes.len())));
+		}
+		bytes
+	})
+}
+
+/// Expression reading field of `ty` according to `attrs`.
+fn read_field(ty: &Type, attrs: &FieldAttrs, endian: Endian, krate: &Tokens, span: &Tokens) -> syn::Result<Tokens> {
+	let endian = attrs.endian.unwrap_or(endian);
+	if let Some(size) = attrs.fixed {
+		if !is_string(ty) {
+			return Err(Error::new_spanned(span, "fixed applies to String fields"));
+		}
+		return Ok(text(quote!({
+			let mut bytes = [0u8; #size];
+			::std::io::Read::read_exact(read, &mut bytes)?;
+			bytes
+		})));
+	}
+	let count = match attrs.count {
+		Some(Count::Prefix(ref int)) => {
+			let int: Type = syn::parse_quote!(#int);
+			let value = read_value(&int, endian, krate);
+			Some(quote!(#value as usize))
+		}
+		Some(Count::Len(ref expr)) => Some(quote!((#expr) as usize)),
+		Some(Count::Rest) => None,
+		None => return Ok(read_value(ty, endian, krate)),
+	};
+	if is_string(ty) {
+		return Ok(text(match count {
+			Some(count) => read_bytes(count),
+			None => quote!({
+				let mut bytes = vec![];
+				::std::io::Read::read_to_end(read, &mut bytes)?;
+				bytes
+			}),
+		}));
+	}
+	let elem = match vec_element(ty) {
+		Some(elem) => elem,
+		None => return Err(Error::new_spanned(span, "prefix, len and rest apply to Vec and String fields")),
+	};
+	Ok(match (count, is_u8(elem)) {
+		(Some(count), true) => read_bytes(count),
+		(None, true) => quote!({
+			let mut bytes = vec![];
+			::std::io::Read::read_to_end(read, &mut bytes)?;
+			bytes
+		}),
+		(Some(count), false) => {
+			let item = read_value(elem, endian, krate);
+			quote!({
+				let count = #count;
+				let mut items = ::std::vec::Vec::new();
+				for _ in 0..count {
+					items.push(#item);
+				}
+				items
+			})
+		}
+		(None, false) => {
+			let item = read_value(elem, endian, krate);
+			quote!({
+				let mut items = ::std::vec::Vec::new();
+				loop {
+					let item: ::std::io::Result<#elem> = (|| Ok(#item))();
+					match item {
+						Ok(item) => items.push(item),
+						Err(ref err) if err.kind() == ::std::io::ErrorKind::UnexpectedEof => break,
+						Err(err) => return Err(err),
+					}
+				}
+				items
+			})
+		}
+	})
+}
+
+/// Statements writing value behind reference `value` of `ty`.
+fn write_value(ty: &Type, endian: Endian, krate: &Tokens, value: Tokens) -> Tokens {
+	if let Some(name) = primitive(ty) {
+		let method = Ident::new(&format!("write_{}", name), Span::call_site());
+		if name == "u8" || name == "i8" {
+			return quote!(::byteorder::WriteBytesExt::#method(write, *#value)?;);
+		}
+		let write = with_order(endian, krate, |order| quote!(::byteorder::WriteBytesExt::#method::<#order>(write, *#value)));
+		return quote!(#write?;);
+	}
+	if let Type::Array(ref array) = *ty {
+		if is_u8(&array.elem) {
+			return quote!(::std::io::Write::write_all(write, &#value[..])?;);
+		}
+		let item = write_value(&array.elem, endian, krate, quote!(item));
+		return quote!(for item in #value.iter() { #item });
+	}
+	let endian = endian_value(endian, krate);
+	quote!(#krate::Ser::ser_endian(#value, write, #endian)?;)
+}
+
+fn too_long(what: Tokens) -> Tokens {
+	quote!(return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput, #what));)
+}
+
+/// Statements writing field behind reference `value` of `ty` according to `attrs`.
+fn write_field(ty: &Type, attrs: &FieldAttrs, endian: Endian, krate: &Tokens, span: &Tokens, value: Tokens) -> syn::Result<Tokens> {
+	let endian = attrs.endian.unwrap_or(endian);
+	if let Some(size) = attrs.fixed {
+		if !is_string(ty) {
+			return Err(Error::new_spanned(span, "fixed applies to String fields"));
+		}
+		let error = too_long(quote!(format!("Text longer than {} bytes: {:?}", #size, #value)));
+		return Ok(quote!({
+			let bytes = #value.as_bytes();
+			if bytes.len() > #size {
+				#error
+			}
+			::std::io::Write::write_all(write, bytes)?;
+			::std::io::Write::write_all(write, &[0u8; #size][bytes.len()..])?;
+		}));
+	}
+	let prefix = match attrs.count {
+		Some(Count::Prefix(ref int)) => {
+			let int: Type = syn::parse_quote!(#int);
+			let error = too_long(quote!(format!("Too many items for {} count: {}", stringify!(#int), count)));
+			let write = write_value(&int, endian, krate, quote!(&(count as #int)));
+			let len = if is_string(ty) { quote!(#value.as_bytes().len()) } else { quote!(#value.len()) };
+			quote!({
+				let count = #len;
+				if count > #int::max_value() as usize {
+					#error
+				}
+				#write
+			})
+		}
+		Some(Count::Len(ref expr)) => {
+			let len = if is_string(ty) { quote!(#value.as_bytes().len()) } else { quote!(#value.len()) };
+			let error = too_long(quote!(format!("Length {} doesn't match {} = {}", #len, stringify!(#expr), expected)));
+			quote!({
+				let expected = (#expr) as usize;
+				if #len != expected {
+					#error
+				}
+			})
+		}
+		Some(Count::Rest) => quote!(),
+		None => return Ok(write_value(ty, endian, krate, value)),
+	};
+	if is_string(ty) {
+		return Ok(quote!(#prefix ::std::io::Write::write_all(write, #value.as_bytes())?;));
+	}
+	let elem = match vec_element(ty) {
+		Some(elem) => elem,
+		None => return Err(Error::new_spanned(span, "prefix, len and rest apply to Vec and String fields")),
+	};
+	if is_u8(elem) {
+		return Ok(quote!(#prefix ::std::io::Write::write_all(write, &#value[..])?;));
+	}
+	let item = write_value(elem, endian, krate, quote!(item));
+	Ok(quote!(#prefix for item in #value.iter() { #item }))
+}
+
+/// Struct has `#[repr(packed)]`, so its fields must be copied before borrowing.
+fn is_packed(attrs: &[Attribute]) -> bool {
+	attrs.iter().any(|attr| attr.path.is_ident("repr") && attr.tokens.to_string().contains("packed"))
+}
+
+/// Byte order and `deser` module path set on struct.
+fn struct_attrs(input: &DeriveInput) -> syn::Result<(Endian, Tokens)> {
+	let attrs = parse_attrs(&input.attrs)?;
+	if attrs.count.is_some() || attrs.fixed.is_some() {
+		return Err(Error::new_spanned(&input.ident, "prefix, len, rest and fixed apply to fields"));
+	}
+	let krate = match attrs.krate {
+		Some(path) => quote!(#path),
+		None => quote!(::deser),
+	};
+	Ok((attrs.endian.unwrap_or(Endian::Runtime), krate))
+}
+
+fn field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+	let attrs = parse_attrs(&field.attrs)?;
+	if attrs.krate.is_some() {
+		return Err(Error::new_spanned(field, "crate applies to structs"));
+	}
+	Ok(attrs)
+}
+
+fn expand(input: DeriveInput) -> syn::Result<Tokens> {
+	let (endian, krate) = struct_attrs(&input)?;
+	let fields = match input.data {
+		Data::Struct(ref data) => &data.fields,
+		_ => return Err(Error::new_spanned(&input.ident, "Deser can be derived for structs only")),
+	};
+	let mut reads = vec![];
+	let mut names = vec![];
+	for (index, field) in fields.iter().enumerate() {
+		let name = match field.ident {
+			Some(ref ident) => ident.clone(),
+			None => Ident::new(&format!("field{}", index), Span::call_site()),
+		};
+		let attrs = field_attrs(field)?;
+		let span = quote!(#field);
+		let read = read_field(&field.ty, &attrs, endian, &krate, &span)?;
+		let ty = &field.ty;
+		reads.push(quote!(let #name: #ty = #read;));
+		names.push(name);
+	}
+	let ident = &input.ident;
+	let construct = match *fields {
+		Fields::Named(_) => quote!(#ident { #(#names: #names),* }),
+		Fields::Unnamed(_) => quote!(#ident ( #(#names),* )),
+		Fields::Unit => quote!(#ident),
+	};
+	let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+	Ok(quote! {
+		impl #impl_generics #krate::Deser for #ident #ty_generics #where_clause {
+			fn deser<R: ::std::io::Read + ::std::fmt::Debug>(read: &mut R) -> ::std::io::Result<Self> {
+				<Self as #krate::Deser>::deser_endian(read, #krate::Endian::Little)
+			}
+			#[allow(unused_variables)]
+			fn deser_endian<R: ::std::io::Read + ::std::fmt::Debug>(read: &mut R, __endian: #krate::Endian) -> ::std::io::Result<Self> {
+				#(#reads)*
+				Ok(#construct)
+			}
+		}
+	})
+}
+
+fn expand_ser(input: DeriveInput) -> syn::Result<Tokens> {
+	let (endian, krate) = struct_attrs(&input)?;
+	let packed = is_packed(&input.attrs);
+	let fields = match input.data {
+		Data::Struct(ref data) => &data.fields,
+		_ => return Err(Error::new_spanned(&input.ident, "Ser can be derived for structs only")),
+	};
+	let mut writes = vec![];
+	let mut bindings = vec![];
+	let mut has_len = false;
+	for (index, field) in fields.iter().enumerate() {
+		let member = match field.ident {
+			Some(ref ident) => quote!(#ident),
+			None => {
+				let index = syn::Index::from(index);
+				quote!(#index)
+			}
+		};
+		// `len` expressions name other fields, as they do when reading.
+		let name = match field.ident {
+			Some(ref ident) => ident.clone(),
+			None => Ident::new(&format!("field{}", index), Span::call_site()),
+		};
+		let ty = &field.ty;
+		bindings.push(if primitive(ty).is_some() {
+			quote!(let #name: #ty = self.#member;)
+		} else if packed {
+			quote!(let #name = { self.#member };)
+		} else {
+			quote!(let #name = &self.#member;)
+		});
+		let attrs = field_attrs(field)?;
+		if let Some(Count::Len(_)) = attrs.count {
+			has_len = true;
+		}
+		let span = quote!(#field);
+		let write = write_field(&field.ty, &attrs, endian, &krate, &span, quote!(value))?;
+		let value = if packed { quote!(&{ self.#member }) } else { quote!(&self.#member) };
+		writes.push(quote!({
+			let value = #value;
+			#write
+		}));
+	}
+	if !has_len {
+		bindings.clear();
+	}
+	let ident = &input.ident;
+	let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+	Ok(quote! {
+		impl #impl_generics #krate::Ser for #ident #ty_generics #where_clause {
+			fn ser<W: ::std::io::Write>(&self, write: &mut W) -> ::std::io::Result<()> {
+				#krate::Ser::ser_endian(self, write, #krate::Endian::Little)
+			}
+			#[allow(unused_variables)]
+			fn ser_endian<W: ::std::io::Write>(&self, write: &mut W, __endian: #krate::Endian) -> ::std::io::Result<()> {
+				#(#bindings)*
+				#(#writes)*
+				Ok(())
+			}
+		}
+	})
+}
+
+#[proc_macro_derive(Deser, attributes(deser))]
+pub fn derive_deser(input: TokenStream) -> TokenStream {
+	let input = syn::parse_macro_input!(input as DeriveInput);
+	match expand(input) {
+		Ok(tokens) => tokens.into(),
+		Err(err) => err.to_compile_error().into(),
+	}
+}
+
+#[proc_macro_derive(Ser, attributes(deser))]
+pub fn derive_ser(input: TokenStream) -> TokenStream {
+	let input = syn::parse_macro_input!(input as DeriveInput);
+	match expand_ser(input) {
+		Ok(tokens) => tokens.into(),
+		Err(err) => err.to_compile_error().into(),
+	}
+}
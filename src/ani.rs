@@ -0,0 +1,300 @@
+use std::io::{self, Read, Seek};
+use std::fmt;
+use std::time::Duration;
+
+use byteorder::{ReadBytesExt, LittleEndian};
+
+use riff;
+use deser::Deser;
+use fourcc::FourCC;
+
+pub const FCC_ACON: FourCC = FourCC([b'A', b'C', b'O', b'N']);
+pub const FCC_ANIH: FourCC = FourCC([b'a', b'n', b'i', b'h']);
+pub const FCC_RATE: FourCC = FourCC([b'r', b'a', b't', b'e']);
+pub const FCC_SEQ:  FourCC = FourCC([b's', b'e', b'q', b' ']);
+pub const FCC_FRAM: FourCC = FourCC([b'f', b'r', b'a', b'm']);
+pub const FCC_ICON: FourCC = FourCC([b'i', b'c', b'o', b'n']);
+pub const FCC_INFO: FourCC = FourCC([b'I', b'N', b'F', b'O']);
+
+/// Frames are stored as ICO/CUR files rather than raw bitmaps.
+pub const AF_ICON: u32 = 0x1;
+/// File has `seq ` chunk.
+pub const AF_SEQUENCE: u32 = 0x2;
+
+/// Jiffies per second, the unit of display rates.
+const JIFFIES: u64 = 60;
+
+fn format_error(message: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32s<R: Read>(read: &mut R) -> io::Result<Vec<u32>> {
+	let mut bytes = vec![];
+	read.read_to_end(&mut bytes)?;
+	let mut bytes = &bytes[..bytes.len() / 4 * 4];
+	let mut result = vec![];
+	while !bytes.is_empty() {
+		result.push(bytes.read_u32::<LittleEndian>()?);
+	}
+	Ok(result)
+}
+
+/// `anih` chunk.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct AniHeader {
+	pub size: u32,
+	pub frames: u32,
+	pub steps: u32,
+	pub width: u32,
+	pub height: u32,
+	pub bit_count: u32,
+	pub planes: u32,
+	/// Default jiffies per step.
+	pub display_rate: u32,
+	pub flags: u32,
+}
+
+impl AniHeader {
+	/// Frames are ICO/CUR files. Otherwise each frame is a `BITMAPINFOHEADER` followed
+	/// by colour table and pixels, without `BITMAPFILEHEADER`.
+	pub fn is_icon(&self) -> bool {
+		self.flags & AF_ICON != 0
+	}
+}
+
+/// Single step of playback sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+	pub frame: u32,
+	/// Display time in 1/60 s.
+	pub jiffies: u32,
+}
+
+impl Step {
+	pub fn duration(&self) -> Duration {
+		let nanos = self.jiffies as u64 * 1_000_000_000 / JIFFIES;
+		Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+	}
+}
+
+/// Parsed `RIFF ACON` animated cursor.
+#[derive(Debug)]
+pub struct AnimatedCursor<'a, T: 'a + Read + Seek + fmt::Debug> {
+	header: AniHeader,
+	steps: Vec<Step>,
+	info: Option<riff::List<'a, T>>,
+	frames: Vec<riff::Chunk<'a, T>>,
+}
+
+impl<'a, T: 'a + Read + Seek + fmt::Debug> AnimatedCursor<'a, T> {
+	pub fn from_riff(riff: &'a mut riff::Riff<T>) -> io::Result<Self> {
+		let mut form = riff.iter().next().ok_or_else(|| format_error("No RIFF form".to_string()))??;
+		if form.fourcc() != FCC_ACON {
+			return Err(format_error(format!("Expected ACON form, found {}", form.fourcc())));
+		}
+		let mut header: Option<AniHeader> = None;
+		let mut rate = None;
+		let mut seq = None;
+		let mut info = None;
+		let mut frames = vec![];
+		for item in form.iter() {
+			match item? {
+				riff::Node::Chunk(mut chunk) => match chunk.fourcc() {
+					FCC_ANIH => header = Some(Deser::deser(&mut chunk.read())?),
+					FCC_RATE => rate = Some(read_u32s(&mut chunk.read())?),
+					FCC_SEQ => seq = Some(read_u32s(&mut chunk.read())?),
+					_ => {}
+				},
+				riff::Node::List(mut list) => match list.fourcc() {
+					FCC_FRAM => {
+						for item in list.iter() {
+							if let riff::Node::Chunk(chunk) = item? {
+								if chunk.fourcc() == FCC_ICON {
+									frames.push(chunk);
+								}
+							}
+						}
+					}
+					FCC_INFO => info = Some(list),
+					_ => {}
+				},
+			}
+		}
+		let header = header.ok_or_else(|| format_error("No anih chunk".to_string()))?;
+		if frames.len() != header.frames as usize {
+			return Err(format_error(format!("anih declares {} frames, found {}", { header.frames }, frames.len())));
+		}
+		let count = header.steps as usize;
+		let seq = match seq {
+			Some(seq) => seq,
+			// Without `seq` each frame is shown once in order. `frames` is already checked against actual icons.
+			None if header.steps == header.frames => (0..header.steps).collect(),
+			None => return Err(format_error(format!("anih declares {} steps for {} frames without seq", { header.steps }, { header.frames }))),
+		};
+		if seq.len() < count {
+			return Err(format_error(format!("seq has {} steps, expected {}", seq.len(), count)));
+		}
+		if let Some(ref rate) = rate {
+			if rate.len() < count {
+				return Err(format_error(format!("rate has {} steps, expected {}", rate.len(), count)));
+			}
+		}
+		let mut steps = vec![];
+		for index in 0..count {
+			if seq[index] as usize >= frames.len() {
+				return Err(format_error(format!("Step {} refers to missing frame {}", index, seq[index])));
+			}
+			steps.push(Step {
+				frame: seq[index],
+				jiffies: rate.as_ref().map_or(header.display_rate, |rate| rate[index]),
+			});
+		}
+		Ok(AnimatedCursor {
+			header: header,
+			steps: steps,
+			info: info,
+			frames: frames,
+		})
+	}
+
+	pub fn header(&self) -> &AniHeader {
+		&self.header
+	}
+
+	/// Playback sequence, one entry per step.
+	pub fn steps(&self) -> &[Step] {
+		&self.steps
+	}
+
+	/// Time of one loop through all steps.
+	pub fn duration(&self) -> Duration {
+		self.steps.iter().map(Step::duration).fold(Duration::new(0, 0), |sum, duration| sum + duration)
+	}
+
+	pub fn frame_count(&self) -> usize {
+		self.frames.len()
+	}
+
+	/// Bytes of frame `index`: ICO/CUR file if `header().is_icon()`, raw bitmap otherwise.
+	pub fn frame_data(&mut self, index: usize) -> io::Result<Vec<u8>> {
+		let count = self.frames.len();
+		let frame = self.frames.get_mut(index).ok_or_else(|| {
+			io::Error::new(io::ErrorKind::InvalidInput, format!("No frame {} of {}", index, count))
+		})?;
+		let mut data = vec![];
+		frame.read().read_to_end(&mut data)?;
+		Ok(data)
+	}
+
+	/// `LIST INFO` with title and author, if present.
+	pub fn info(&mut self) -> Option<&mut riff::List<'a, T>> {
+		self.info.as_mut()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+	use std::time::Duration;
+
+	use deser::Ser;
+	use riff::{Riff, RiffWriter};
+	use super::*;
+
+	fn u32s(values: &[u32]) -> Vec<u8> {
+		values.iter().flat_map(|&value| vec![value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]).collect()
+	}
+
+	fn file(frames: u32, steps: u32, icons: u32, seq: Option<&[u32]>, rate: Option<&[u32]>) -> Vec<u8> {
+		build(AF_ICON, frames, steps, icons, seq, rate)
+	}
+
+	fn build(flags: u32, frames: u32, steps: u32, icons: u32, seq: Option<&[u32]>, rate: Option<&[u32]>) -> Vec<u8> {
+		let header = AniHeader {
+			size: 36,
+			frames: frames,
+			steps: steps,
+			width: 0,
+			height: 0,
+			bit_count: 0,
+			planes: 0,
+			display_rate: 6,
+			flags: flags | if seq.is_some() { AF_SEQUENCE } else { 0 },
+		};
+		let mut anih = vec![];
+		header.ser(&mut anih).unwrap();
+		let mut writer = RiffWriter::new(Cursor::new(vec![]));
+		writer.begin_riff(FCC_ACON).unwrap();
+		writer.write_chunk(FCC_ANIH, &anih).unwrap();
+		if let Some(rate) = rate {
+			writer.write_chunk(FCC_RATE, &u32s(rate)).unwrap();
+		}
+		if let Some(seq) = seq {
+			writer.write_chunk(FCC_SEQ, &u32s(seq)).unwrap();
+		}
+		writer.begin_list(FCC_FRAM).unwrap();
+		for index in 0..icons {
+			writer.write_chunk(FCC_ICON, &[0, 0, 2, 0, index as u8]).unwrap();
+		}
+		writer.end().unwrap();
+		writer.end().unwrap();
+		writer.finish().unwrap().into_inner()
+	}
+
+	#[test]
+	fn default_sequence() {
+		let mut riff = Riff::new(Cursor::new(file(3, 3, 3, None, None))).unwrap();
+		let mut ani = AnimatedCursor::from_riff(&mut riff).unwrap();
+		assert_eq!(ani.frame_count(), 3);
+		assert_eq!(ani.steps().iter().map(|step| step.frame).collect::<Vec<_>>(), vec![0, 1, 2]);
+		assert_eq!(ani.duration(), Duration::from_millis(300));
+		assert_eq!(ani.frame_data(2).unwrap(), vec![0, 0, 2, 0, 2]);
+		assert!(ani.header().is_icon());
+	}
+
+	#[test]
+	fn bad_frame_index() {
+		let mut riff = Riff::new(Cursor::new(file(2, 2, 2, None, None))).unwrap();
+		let mut ani = AnimatedCursor::from_riff(&mut riff).unwrap();
+		assert_eq!(ani.frame_data(2).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+	}
+
+	#[test]
+	fn raw_bitmap_frames() {
+		let mut riff = Riff::new(Cursor::new(build(0, 1, 1, 1, None, None))).unwrap();
+		let ani = AnimatedCursor::from_riff(&mut riff).unwrap();
+		assert!(!ani.header().is_icon());
+	}
+
+	#[test]
+	fn sequence_and_rates() {
+		let data = file(2, 4, 2, Some(&[0, 1, 1, 0]), Some(&[30, 15, 15, 60]));
+		let mut riff = Riff::new(Cursor::new(data)).unwrap();
+		let ani = AnimatedCursor::from_riff(&mut riff).unwrap();
+		assert_eq!(ani.steps(), &[
+			Step { frame: 0, jiffies: 30 },
+			Step { frame: 1, jiffies: 15 },
+			Step { frame: 1, jiffies: 15 },
+			Step { frame: 0, jiffies: 60 },
+		]);
+		assert_eq!(ani.duration(), Duration::from_secs(2));
+	}
+
+	#[test]
+	fn huge_step_count_without_seq() {
+		let mut riff = Riff::new(Cursor::new(file(2, 0xFFFFFFFF, 2, None, None))).unwrap();
+		let err = AnimatedCursor::from_riff(&mut riff).unwrap_err();
+		assert!(err.to_string().contains("without seq"), "{}", err);
+	}
+
+	#[test]
+	fn bad_references() {
+		let mut riff = Riff::new(Cursor::new(file(3, 3, 2, None, None))).unwrap();
+		assert!(AnimatedCursor::from_riff(&mut riff).is_err());
+		let mut riff = Riff::new(Cursor::new(file(2, 2, 2, Some(&[0, 2]), None))).unwrap();
+		assert!(AnimatedCursor::from_riff(&mut riff).is_err());
+		let mut riff = Riff::new(Cursor::new(file(2, 3, 2, Some(&[0, 1]), None))).unwrap();
+		assert!(AnimatedCursor::from_riff(&mut riff).is_err());
+	}
+}
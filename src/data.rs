@@ -1,8 +1,8 @@
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::borrow::BorrowMut;
 use std::mem::size_of;
 
-use deser::{Deser, PlainOldData};
+use deser::{Deser, Ser};
 use fourcc::FourCC;
 
 /*
@@ -31,13 +31,21 @@ pub const FCC_AUDS: FourCC = FourCC([b'a', b'u', b'd', b's']);
 pub const FCC_TXTS: FourCC = FourCC([b't', b'x', b't', b's']);
 pub const FCC_JUNK: FourCC = FourCC([b'J', b'U', b'N', b'K']);
 
+pub const FCC_WAVE: FourCC = FourCC([b'W', b'A', b'V', b'E']);
+pub const FCC_FMT:  FourCC = FourCC([b'f', b'm', b't', b' ']);
+pub const FCC_FACT: FourCC = FourCC([b'f', b'a', b'c', b't']);
+pub const FCC_DATA: FourCC = FourCC([b'd', b'a', b't', b'a']);
+
 
 pub const AVI_INDEX_OF_INDEXES: u8 = 0; // Not set
 pub const AVI_INDEX_2FIELD: u8 = 2;
 
+pub const BI_BITFIELDS: u32 = 3;
+pub const BI_ALPHABITFIELDS: u32 = 6;
+
 
 #[repr(C, packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
 pub struct BITMAPINFOHEADER {
     pub size: u32,
     pub width: i32,
@@ -51,13 +59,123 @@ pub struct BITMAPINFOHEADER {
     pub clr_used: u32,
     pub clr_important: u32,
 }
-unsafe impl PlainOldData for BITMAPINFOHEADER {}
 
 pub type BitmapInfoHeader = BITMAPINFOHEADER;
 
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deser, Ser)]
+pub struct RGBQUAD {
+    pub blue: u8,
+    pub green: u8,
+    pub red: u8,
+    pub reserved: u8,
+}
 
 #[repr(C, packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deser, Ser)]
+pub struct PALETTEENTRY {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub flags: u8,
+}
+
+pub type PaletteEntry = PALETTEENTRY;
+
+/// Colour table of indexed bitmaps and `RIFF PAL ` files.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Palette {
+    pub entries: Vec<PaletteEntry>,
+}
+
+impl Palette {
+    /// Palette from `BITMAPINFO` colour table.
+    pub fn from_rgbquads(colors: &[RGBQUAD]) -> Self {
+        Palette {
+            entries: colors.iter().map(|color| PaletteEntry { red: color.red, green: color.green, blue: color.blue, flags: 0 }).collect(),
+        }
+    }
+    /// `BITMAPINFO` colour table. Entry flags are dropped.
+    pub fn to_rgbquads(&self) -> Vec<RGBQUAD> {
+        self.entries.iter().map(|entry| RGBQUAD { blue: entry.blue, green: entry.green, red: entry.red, reserved: 0 }).collect()
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// `BITMAPINFOHEADER` followed by colour table.
+#[derive(Clone, Debug)]
+pub struct BitmapInfo {
+    pub header: BITMAPINFOHEADER,
+    /// Bytes between `header` and colour table: fields of V4/V5 headers,
+    /// or `BI_BITFIELDS` colour masks following plain header.
+    pub extra: Vec<u8>,
+    pub palette: Palette,
+}
+
+impl BitmapInfo {
+    /// Number of colour table entries declared by header.
+    pub fn color_count(header: &BITMAPINFOHEADER) -> usize {
+        match (header.clr_used, header.bit_count) {
+            (0, bits) if bits <= 8 => 1 << bits,
+            (used, _) => used as usize,
+        }
+    }
+
+    /// Number of bytes between header and colour table.
+    pub fn extra_size(header: &BITMAPINFOHEADER) -> u64 {
+        let extra = (header.size as u64).saturating_sub(size_of::<BITMAPINFOHEADER>() as u64);
+        match header.compression {
+            // Masks are part of V4/V5 headers, and follow plain header otherwise.
+            BI_BITFIELDS if extra == 0 => 3 * 4,
+            BI_ALPHABITFIELDS if extra == 0 => 4 * 4,
+            _ => extra,
+        }
+    }
+}
+
+impl Deser for BitmapInfo {
+    fn deser<R: Read+::std::fmt::Debug>(read: &mut R) -> io::Result<BitmapInfo> {
+        let read = read.borrow_mut();
+        let header = BITMAPINFOHEADER::deser(read)?;
+        let size = BitmapInfo::extra_size(&header);
+        let mut extra = vec![];
+        read.by_ref().take(size).read_to_end(&mut extra)?;
+        if (extra.len() as u64) < size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("BITMAPINFO is truncated: {} of {} extra bytes", extra.len(), size)));
+        }
+        let mut colors = vec![];
+        for _ in 0..BitmapInfo::color_count(&header) {
+            match RGBQUAD::deser(read) {
+                Ok(color) => colors.push(color),
+                // Colour table is often truncated or missing in `strf`.
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(BitmapInfo { header: header, extra: extra, palette: Palette::from_rgbquads(&colors) })
+    }
+}
+
+impl Ser for BitmapInfo {
+    fn ser<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let size = BitmapInfo::extra_size(&self.header);
+        if self.extra.len() as u64 != size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("BITMAPINFO header needs {} extra bytes, found {}", size, self.extra.len())));
+        }
+        self.header.ser(write)?;
+        write.write_all(&self.extra)?;
+        self.palette.to_rgbquads().ser(write)
+    }
+}
+
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
 pub struct WAVEFORMATEX {
     pub format_tag: u16,
     pub channels: u16,
@@ -67,27 +185,34 @@ pub struct WAVEFORMATEX {
     pub bits_per_sample: u16,
     pub size: u16,
 }
-unsafe impl PlainOldData for WAVEFORMATEX {}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deser, Ser)]
 pub struct WaveFormat {
     header: WAVEFORMATEX,
+    #[deser(len = "header.size")]
     extra: Vec<u8>,
 }
 
-
-impl Deser for WaveFormat {
-    fn deser<R: Read+::std::fmt::Debug>(read: &mut R) -> io::Result<WaveFormat> {
-        let read = read.borrow_mut();
-        let header = WAVEFORMATEX::deser(read)?;
-        let mut extra = vec![0; header.size as usize];
-        read.read_exact(&mut extra[..])?;
+impl WaveFormat {
+    /// Format with `extra` bytes. `size` of header is set to their length, which must fit `u16`.
+    pub fn new(mut header: WAVEFORMATEX, extra: Vec<u8>) -> io::Result<Self> {
+        if extra.len() > u16::max_value() as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Too many extra format bytes: {}", extra.len())));
+        }
+        header.size = extra.len() as u16;
         Ok(WaveFormat { header: header, extra: extra })
     }
+    pub fn header(&self) -> &WAVEFORMATEX {
+        &self.header
+    }
+    /// Format specific bytes following `WAVEFORMATEX`.
+    pub fn extra(&self) -> &[u8] {
+        &self.extra
+    }
 }
 
 #[repr(C, packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
 pub struct MainHeader {
     pub micro_sec_per_frame: u32,
     pub max_bytes_per_sec: u32,
@@ -101,7 +226,6 @@ pub struct MainHeader {
     pub height: u32,
     pub reserved: [u32; 4],
 }
-unsafe impl PlainOldData for MainHeader {}
 
 #[derive(Copy, Clone, Debug)]
 pub enum Flag {
@@ -115,17 +239,16 @@ pub enum Flag {
 
 
 #[repr(C, packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
 pub struct RECT {
     pub left: i16,
     pub top: i16,
     pub right: i16,
     pub bottom: i16,
 }
-unsafe impl PlainOldData for RECT {}
 
 #[repr(C, packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
 pub struct StreamHeader {
     pub fcc_type: FourCC,
     pub fcc_handler: FourCC,
@@ -142,7 +265,6 @@ pub struct StreamHeader {
     pub sample_size: u32,
     pub frame: RECT,
 }
-unsafe impl PlainOldData for StreamHeader {}
 
 #[derive(Copy, Clone, Debug)]
 pub enum StreamFlag {
@@ -152,14 +274,13 @@ pub enum StreamFlag {
 
 
 #[repr(C, packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
 pub struct IndexEntry {
     pub ckid: u32,
     pub flags: u32,
     pub chunk_offset: u32,
     pub chunk_length: u32,
 }
-unsafe impl PlainOldData for IndexEntry {}
 
 #[derive(Copy, Clone, Debug)]
 pub enum IndexFlags {
@@ -171,7 +292,7 @@ pub enum IndexFlags {
 }
 
 #[repr(C, packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
 pub struct StreamIndexHeader {
     pub fcc: FourCC,
     pub cb: u32,
@@ -182,18 +303,16 @@ pub struct StreamIndexHeader {
     pub chunk_id: u32,
     pub reserved: [u32;3],
 }
-unsafe impl PlainOldData for StreamIndexHeader {}
 
 
 #[repr(C, packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
 pub struct StreamIndexEntry { adw: u32 }
-unsafe impl PlainOldData for StreamIndexEntry {}
 
 
 
 #[repr(C, packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
 pub struct SuperIndexHeader {
     pub fcc: FourCC,
     pub cb: u32,
@@ -204,10 +323,140 @@ pub struct SuperIndexHeader {
     pub chunk_id: u32,
     pub reserved: [u32;3],
 }
-unsafe impl PlainOldData for SuperIndexHeader {}
 
 
 #[repr(C, packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
 pub struct SuperIndexEntry { offset: u64, size: u32, duration: u32 }
-unsafe impl PlainOldData for SuperIndexEntry {}
+
+#[cfg(test)]
+mod tests {
+    use deser::{Deser, Ser};
+    use super::*;
+
+    /// `BITMAPINFOHEADER` of `size` bytes, `bits` per pixel, `compression` and `clr_used`.
+    fn header(size: u32, bits: u16, compression: u32, clr_used: u32) -> Vec<u8> {
+        let mut data = vec![];
+        for &value in &[size, 16, 8] {
+            data.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+        }
+        data.extend_from_slice(&[1, 0, bits as u8, (bits >> 8) as u8]);
+        for &value in &[compression, 0, 0, 0, clr_used, 0] {
+            data.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+        }
+        data
+    }
+
+    #[test]
+    fn palette() {
+        let mut data = header(40, 8, 0, 2);
+        data.extend_from_slice(&[1, 2, 3, 0, 4, 5, 6, 0]);
+        let info = BitmapInfo::deser(&mut &data[..]).unwrap();
+        assert!(info.extra.is_empty());
+        assert_eq!(info.palette.entries, vec![
+            PaletteEntry { red: 3, green: 2, blue: 1, flags: 0 },
+            PaletteEntry { red: 6, green: 5, blue: 4, flags: 0 },
+        ]);
+    }
+
+    #[test]
+    fn truncated_palette() {
+        let mut data = header(40, 4, 0, 0);
+        data.extend_from_slice(&[1, 2, 3, 0]);
+        let info = BitmapInfo::deser(&mut &data[..]).unwrap();
+        assert_eq!(info.palette.len(), 1);
+    }
+
+    #[test]
+    fn bitfield_masks() {
+        let mut data = header(40, 16, BI_BITFIELDS, 0);
+        let masks = [0x00, 0xf8, 0, 0, 0xe0, 0x07, 0, 0, 0x1f, 0, 0, 0];
+        data.extend_from_slice(&masks);
+        let info = BitmapInfo::deser(&mut &data[..]).unwrap();
+        assert_eq!(info.extra, masks.to_vec());
+        assert!(info.palette.entries.is_empty());
+    }
+
+    #[test]
+    fn v5_header() {
+        let mut data = header(124, 8, BI_BITFIELDS, 1);
+        let v5 = (0..84).collect::<Vec<u8>>();
+        data.extend_from_slice(&v5);
+        data.extend_from_slice(&[9, 8, 7, 0]);
+        let info = BitmapInfo::deser(&mut &data[..]).unwrap();
+        assert_eq!(info.extra, v5);
+        assert_eq!(info.palette.entries, vec![PaletteEntry { red: 7, green: 8, blue: 9, flags: 0 }]);
+    }
+
+    #[test]
+    fn truncated_extra() {
+        let mut data = header(124, 24, 0, 0);
+        data.extend_from_slice(&[0; 10]);
+        assert!(BitmapInfo::deser(&mut &data[..]).is_err());
+    }
+
+    #[test]
+    fn bitmap_info_round_trip() {
+        let mut bitfields = header(40, 16, BI_BITFIELDS, 0);
+        bitfields.extend_from_slice(&[0x00, 0xf8, 0, 0, 0xe0, 0x07, 0, 0, 0x1f, 0, 0, 0]);
+        let mut v5 = header(124, 8, 0, 2);
+        v5.extend((0..84).map(|i| i as u8));
+        v5.extend_from_slice(&[1, 2, 3, 0, 4, 5, 6, 0]);
+        for data in &[bitfields, v5] {
+            let info = BitmapInfo::deser(&mut &data[..]).unwrap();
+            let mut written = vec![];
+            info.ser(&mut written).unwrap();
+            assert_eq!(&written, data);
+        }
+    }
+
+    #[test]
+    fn bitmap_info_extra_mismatch() {
+        let data = header(124, 24, 0, 0);
+        let info = BitmapInfo {
+            header: BITMAPINFOHEADER::deser(&mut &data[..]).unwrap(),
+            extra: vec![],
+            palette: Palette { entries: vec![] },
+        };
+        assert_eq!(info.ser(&mut vec![]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn wave_format_round_trip() {
+        let data = b"\xfe\xff\x02\0\x80\xbb\0\0\0\xee\x02\0\x04\0\x10\0\x03\0abc";
+        let format = WaveFormat::deser(&mut &data[..]).unwrap();
+        assert_eq!(format.extra(), b"abc");
+        let rebuilt = WaveFormat::new(*format.header(), b"xyzw".to_vec()).unwrap();
+        assert_eq!({ rebuilt.header().size }, 4);
+        let mut written = vec![];
+        format.ser(&mut written).unwrap();
+        assert_eq!(&written[..], &data[..]);
+    }
+
+    #[test]
+    fn wave_format_extra_limit() {
+        let header = *WaveFormat::deser(&mut &[0; 18][..]).unwrap().header();
+        let err = WaveFormat::new(header, vec![0; 0x10000]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn stream_header_fields() {
+        let data = b"vidsDIB \x01\0\0\0\x02\0\x03\0\x04\0\0\0\x01\0\0\0\x19\0\0\0\
+                     \0\0\0\0\x2c\x01\0\0\0\x00\x01\0\xff\xff\xff\xff\0\0\0\0\
+                     \xfe\xff\x01\0\x40\x01\xf0\0";
+        let header = StreamHeader::deser(&mut &data[..]).unwrap();
+        assert_eq!(header.fcc_type, FourCC::from_str("vids").unwrap());
+        assert_eq!(header.fcc_handler, FourCC::from_str("DIB ").unwrap());
+        assert_eq!(({ header.flags }, { header.priority }, { header.language }), (1, 2, 3));
+        assert_eq!(({ header.initial_frams }, { header.scale }, { header.rate }), (4, 1, 25));
+        assert_eq!(({ header.start }, { header.length }), (0, 300));
+        assert_eq!(({ header.suggested_buffer_suze }, { header.quality }, { header.sample_size }), (0x10000, 0xffffffff, 0));
+        let frame = header.frame;
+        assert_eq!(({ frame.left }, { frame.top }, { frame.right }, { frame.bottom }), (-2, 1, 320, 240));
+        let mut written = vec![];
+        header.ser(&mut written).unwrap();
+        assert_eq!(&written[..], &data[..]);
+    }
+}
+
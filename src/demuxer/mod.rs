@@ -30,11 +30,20 @@ pub enum AVIError {
 impl From<io::Error> for AVIError {
 	fn from(err: io::Error) -> Self { IOError(err) }
 }
+
+impl From<AVIError> for io::Error {
+	fn from(err: AVIError) -> Self {
+		match err {
+			IOError(err) => err,
+			err => io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)),
+		}
+	}
+}
 pub type AVIResult<T> = Result<T, AVIError>;
 
 #[derive(Clone, Debug)]
 pub enum Format {
-	Video(BitmapInfoHeader),
+	Video(BitmapInfo),
 	Audio(WaveFormat),
 }
 
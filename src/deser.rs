@@ -1,36 +1,47 @@
+//! Binary records: `Deser` reads them and `Ser` writes them back, see `avi_derive`.
+
 use std::borrow::BorrowMut;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
+
+pub use riff::Endian;
 
 pub trait Deser: Sized {
+	/// Decode little endian record.
 	fn deser<R: Read+::std::fmt::Debug>(read: &mut R) -> io::Result<Self>;
+
+	/// Decode record with integers in byte order `endian`, as given by `Chunk::endian`.
+	/// Records without big endian layout refuse `Endian::Big`.
+	fn deser_endian<R: Read+::std::fmt::Debug>(read: &mut R, endian: Endian) -> io::Result<Self> {
+		match endian {
+			Endian::Little => Self::deser(read),
+			Endian::Big => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} has no big endian layout", ::typename::<Self>()))),
+		}
+	}
 }
 
-pub unsafe trait PlainOldData: Copy + Sized {}
+pub trait Ser {
+	/// Encode little endian record.
+	fn ser<W: Write>(&self, write: &mut W) -> io::Result<()>;
 
-impl<T> Deser for T where T: PlainOldData {
-    fn deser<R: Read+::std::fmt::Debug>(read: &mut R) -> io::Result<T> {
-    	let mut read = read.borrow_mut();
-        unsafe {
-            let mut value = ::std::mem::uninitialized();
-            let buf = ::std::slice::from_raw_parts_mut(&mut value as *mut Self as *mut u8,
-                                                       ::std::mem::size_of::<Self>());
-            match read.read_exact(buf) {
-                Ok(()) => Ok(value),
-                Err(err) => {
-                    ::std::mem::forget(value);
-                    println!("Failed to parse {} from {:?} cause of {}", ::typename::<T>(), read, err);
-                    Err(err)
-                }
-            }
-        }
-    }
+	/// Encode record with integers in byte order `endian`.
+	/// Records without big endian layout refuse `Endian::Big`.
+	fn ser_endian<W: Write>(&self, write: &mut W, endian: Endian) -> io::Result<()> {
+		match endian {
+			Endian::Little => self.ser(write),
+			Endian::Big => Err(io::Error::new(io::ErrorKind::InvalidInput, "Record has no big endian layout")),
+		}
+	}
 }
 
 impl<T> Deser for Vec<T> where T: Deser {
 	fn deser<R: Read+::std::fmt::Debug>(read: &mut R) -> io::Result<Vec<T>> {
+		Self::deser_endian(read, Endian::Little)
+	}
+
+	fn deser_endian<R: Read+::std::fmt::Debug>(read: &mut R, endian: Endian) -> io::Result<Vec<T>> {
 		let mut result = vec![];
 		loop {
-			match T::deser(read) {
+			match T::deser_endian(read, endian) {
 				Ok(value) => result.push(value),
 				Err(err) => {
 					if err.kind() == io::ErrorKind::UnexpectedEof {
@@ -57,4 +68,195 @@ impl Deser for String {
 			}
 		}
 	}
+
+	fn deser_endian<R: Read+::std::fmt::Debug>(read: &mut R, _: Endian) -> io::Result<String> {
+		Self::deser(read)
+	}
+}
+
+impl<T> Ser for Vec<T> where T: Ser {
+	fn ser<W: Write>(&self, write: &mut W) -> io::Result<()> {
+		self.ser_endian(write, Endian::Little)
+	}
+
+	fn ser_endian<W: Write>(&self, write: &mut W, endian: Endian) -> io::Result<()> {
+		for item in self {
+			item.ser_endian(write, endian)?;
+		}
+		Ok(())
+	}
+}
+
+impl Ser for String {
+	fn ser<W: Write>(&self, write: &mut W) -> io::Result<()> {
+		write.write_all(self.as_bytes())
+	}
+
+	fn ser_endian<W: Write>(&self, write: &mut W, _: Endian) -> io::Result<()> {
+		self.ser(write)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::ErrorKind;
+
+	use riff::Ds64;
+	use super::{Deser, Ser, Endian};
+
+	#[derive(Clone, Debug, PartialEq, Deser, Ser)]
+	struct Item {
+		id: u16,
+		#[deser(big)]
+		value: u32,
+	}
+
+	#[derive(Clone, Debug, PartialEq, Deser, Ser)]
+	struct Record {
+		kind: [u8; 2],
+		size: u8,
+		#[deser(len = "size")]
+		bytes: Vec<u8>,
+		#[deser(prefix = "u16")]
+		items: Vec<Item>,
+		#[deser(fixed = 6)]
+		name: String,
+		#[deser(prefix = "u8")]
+		label: String,
+		#[deser(rest)]
+		rest: String,
+	}
+
+	#[derive(Debug, PartialEq, Deser, Ser)]
+	#[deser(big)]
+	struct Big(u16, i32, [u16; 2], #[deser(little)] u32);
+
+	const RECORD: &'static [u8] = b"ab\x02xy\x01\0\x07\0\0\0\x01\x02abc\0\0\0\x02hitail";
+
+	fn record() -> Record {
+		Record {
+			kind: *b"ab",
+			size: 2,
+			bytes: b"xy".to_vec(),
+			items: vec![Item { id: 7, value: 0x102 }],
+			name: "abc".to_string(),
+			label: "hi".to_string(),
+			rest: "tail".to_string(),
+		}
+	}
+
+	#[test]
+	fn derived_layout() {
+		assert_eq!(Record::deser(&mut &RECORD[..]).unwrap(), record());
+		let mut data = vec![];
+		record().ser(&mut data).unwrap();
+		assert_eq!(&data[..], RECORD);
+	}
+
+	#[test]
+	fn round_trip() {
+		let records = vec![
+			record(),
+			Record {
+				kind: [0, 0xff],
+				size: 0,
+				bytes: vec![],
+				items: vec![],
+				name: "abcdef".to_string(),
+				label: String::new(),
+				rest: String::new(),
+			},
+		];
+		for record in records {
+			let mut data = vec![];
+			record.ser(&mut data).unwrap();
+			assert_eq!(Record::deser(&mut &data[..]).unwrap(), record);
+		}
+		let big = Big(0xfffe, i32::min_value(), [1, 0x8000], 0x01020304);
+		let mut data = vec![];
+		big.ser(&mut data).unwrap();
+		assert_eq!(&data[data.len() - 4..], &[4, 3, 2, 1]);
+		assert_eq!(Big::deser(&mut &data[..]).unwrap(), big);
+	}
+
+	#[test]
+	fn len_mismatch() {
+		let mut record = record();
+		record.size = 3;
+		assert_eq!(record.ser(&mut vec![]).unwrap_err().kind(), ErrorKind::InvalidInput);
+		record.bytes.push(b'z');
+		let mut data = vec![];
+		record.ser(&mut data).unwrap();
+		assert_eq!(Record::deser(&mut &data[..]).unwrap(), record);
+	}
+
+	#[test]
+	fn struct_byte_order() {
+		let data = b"\x01\x02\xff\xff\xff\xfe\0\x03\0\x04\x05\0\0\0";
+		let big = Big::deser(&mut &data[..]).unwrap();
+		assert_eq!(big, Big(0x102, -2, [3, 4], 5));
+		let mut written = vec![];
+		big.ser(&mut written).unwrap();
+		assert_eq!(&written[..], &data[..]);
+	}
+
+	#[test]
+	fn huge_counts() {
+		let mut data = RECORD.to_vec();
+		data[2] = 0xff;
+		assert_eq!(Record::deser(&mut &data[..]).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+		let mut data = RECORD.to_vec();
+		data[5] = 0xff;
+		data[6] = 0xff;
+		assert_eq!(Record::deser(&mut &data[..]).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+	}
+
+	#[test]
+	fn write_limits() {
+		let mut long = record();
+		long.name = "abcdefg".to_string();
+		assert_eq!(long.ser(&mut vec![]).unwrap_err().kind(), ErrorKind::InvalidInput);
+		let mut many = record();
+		many.items = vec![Item { id: 0, value: 0 }; 0x10000];
+		assert_eq!(many.ser(&mut vec![]).unwrap_err().kind(), ErrorKind::InvalidInput);
+		let mut label = record();
+		label.label = "x".repeat(256);
+		assert_eq!(label.ser(&mut vec![]).unwrap_err().kind(), ErrorKind::InvalidInput);
+	}
+
+	#[test]
+	fn runtime_byte_order() {
+		// `Item::value` is always big endian, `id` and the count follow the caller.
+		let data = b"\0\x02\0\x07\0\0\x01\x02\0\x08\0\0\x01\x03";
+		let items = Vec::<Item>::deser_endian(&mut &data[2..], Endian::Big).unwrap();
+		assert_eq!(items, vec![Item { id: 7, value: 0x102 }, Item { id: 8, value: 0x103 }]);
+		let mut written = vec![];
+		items.ser_endian(&mut written, Endian::Big).unwrap();
+		assert_eq!(&written[..], &data[2..]);
+
+		let mut record = record();
+		record.items = items;
+		let mut big = vec![];
+		record.ser_endian(&mut big, Endian::Big).unwrap();
+		assert_eq!(&big[5..9], &data[..4]);
+		assert_eq!(Record::deser_endian(&mut &big[..], Endian::Big).unwrap(), record);
+		let mut little = vec![];
+		record.ser_endian(&mut little, Endian::Little).unwrap();
+		let mut plain = vec![];
+		record.ser(&mut plain).unwrap();
+		assert_eq!(little, plain);
+
+		// Struct level order wins over the caller.
+		let fixed = Big(1, 2, [3, 4], 5);
+		let mut written = vec![];
+		fixed.ser_endian(&mut written, Endian::Little).unwrap();
+		assert_eq!(&written[..2], &[0, 1]);
+	}
+
+	#[test]
+	fn little_endian_only_records() {
+		let data = [0; 28];
+		assert_eq!(Ds64::deser_endian(&mut &data[..], Endian::Big).unwrap_err().kind(), ErrorKind::InvalidData);
+		assert!(Ds64::deser_endian(&mut &data[..], Endian::Little).is_ok());
+	}
 }
@@ -0,0 +1,594 @@
+use std::io::{self, Read, Seek};
+use std::fmt;
+
+use byteorder::{ReadBytesExt, LittleEndian};
+
+use riff;
+use wav;
+use data::{WaveFormat, FCC_FMT, FCC_DATA};
+use deser::Deser;
+use fourcc::FourCC;
+
+pub const FCC_DLS:  FourCC = FourCC([b'D', b'L', b'S', b' ']);
+pub const FCC_COLH: FourCC = FourCC([b'c', b'o', b'l', b'h']);
+pub const FCC_VERS: FourCC = FourCC([b'v', b'e', b'r', b's']);
+pub const FCC_LINS: FourCC = FourCC([b'l', b'i', b'n', b's']);
+pub const FCC_INS:  FourCC = FourCC([b'i', b'n', b's', b' ']);
+pub const FCC_INSH: FourCC = FourCC([b'i', b'n', b's', b'h']);
+pub const FCC_LRGN: FourCC = FourCC([b'l', b'r', b'g', b'n']);
+pub const FCC_RGN:  FourCC = FourCC([b'r', b'g', b'n', b' ']);
+pub const FCC_RGN2: FourCC = FourCC([b'r', b'g', b'n', b'2']);
+pub const FCC_RGNH: FourCC = FourCC([b'r', b'g', b'n', b'h']);
+pub const FCC_WSMP: FourCC = FourCC([b'w', b's', b'm', b'p']);
+pub const FCC_WLNK: FourCC = FourCC([b'w', b'l', b'n', b'k']);
+pub const FCC_LART: FourCC = FourCC([b'l', b'a', b'r', b't']);
+pub const FCC_LAR2: FourCC = FourCC([b'l', b'a', b'r', b'2']);
+pub const FCC_ART1: FourCC = FourCC([b'a', b'r', b't', b'1']);
+pub const FCC_ART2: FourCC = FourCC([b'a', b'r', b't', b'2']);
+pub const FCC_PTBL: FourCC = FourCC([b'p', b't', b'b', b'l']);
+pub const FCC_WVPL: FourCC = FourCC([b'w', b'v', b'p', b'l']);
+pub const FCC_WAVE: FourCC = FourCC([b'w', b'a', b'v', b'e']);
+pub const FCC_INFO: FourCC = FourCC([b'I', b'N', b'F', b'O']);
+pub const FCC_INAM: FourCC = FourCC([b'I', b'N', b'A', b'M']);
+
+/// Bit of `Locale::bank` marking drum instruments.
+pub const F_INSTRUMENT_DRUMS: u32 = 0x8000_0000;
+
+fn format_error(message: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn skip<R: Read>(read: &mut R, size: u64) -> io::Result<()> {
+	io::copy(&mut read.take(size), &mut io::sink()).map(|_| ())
+}
+
+/// Text of `INAM` in `LIST INFO`.
+fn read_name<'a, T: 'a + Read + Seek + fmt::Debug>(list: &mut riff::List<'a, T>) -> io::Result<Option<String>> {
+	for item in list.iter() {
+		if let riff::Node::Chunk(mut chunk) = item? {
+			if chunk.fourcc() == FCC_INAM {
+				let mut bytes = vec![];
+				chunk.read().read_to_end(&mut bytes)?;
+				let end = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+				return Ok(Some(String::from_utf8_lossy(&bytes[..end]).into_owned()));
+			}
+		}
+	}
+	Ok(None)
+}
+
+/*
+
+    Fixed size chunks
+
+*/
+
+/// `colh` chunk.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct CollectionHeader {
+	pub instruments: u32,
+}
+
+/// `vers` chunk.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct Version {
+	pub ms: u32,
+	pub ls: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct Locale {
+	pub bank: u32,
+	pub instrument: u32,
+}
+
+/// `insh` chunk.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct InstrumentHeader {
+	pub regions: u32,
+	pub locale: Locale,
+}
+
+/// `wlnk` chunk.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct WaveLink {
+	pub options: u16,
+	pub phase_group: u16,
+	pub channel: u32,
+	/// Index into pool table.
+	pub table_index: u32,
+}
+
+/// Connection block of `art1`/`art2`.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct ConnectionBlock {
+	pub source: u16,
+	pub control: u16,
+	pub destination: u16,
+	pub transform: u16,
+	pub scale: i32,
+}
+
+/*
+
+    Variable size chunks
+
+*/
+
+/// `rgnh` chunk. `layer` is present in DLS2 files only.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RegionHeader {
+	pub key_low: u16,
+	pub key_high: u16,
+	pub velocity_low: u16,
+	pub velocity_high: u16,
+	pub options: u16,
+	pub key_group: u16,
+	pub layer: Option<u16>,
+}
+
+impl RegionHeader {
+	pub fn read<R: Read>(read: &mut R, size: u64) -> io::Result<Self> {
+		Ok(RegionHeader {
+			key_low: read.read_u16::<LittleEndian>()?,
+			key_high: read.read_u16::<LittleEndian>()?,
+			velocity_low: read.read_u16::<LittleEndian>()?,
+			velocity_high: read.read_u16::<LittleEndian>()?,
+			options: read.read_u16::<LittleEndian>()?,
+			key_group: read.read_u16::<LittleEndian>()?,
+			layer: if size >= 14 { Some(read.read_u16::<LittleEndian>()?) } else { None },
+		})
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WaveSampleLoop {
+	/// 0 - forward, 1 - release.
+	pub kind: u32,
+	pub start: u32,
+	pub length: u32,
+}
+
+/// `wsmp` chunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WaveSample {
+	pub unity_note: u16,
+	pub fine_tune: i16,
+	pub gain: i32,
+	pub options: u32,
+	pub loops: Vec<WaveSampleLoop>,
+}
+
+impl WaveSample {
+	pub fn read<R: Read>(read: &mut R) -> io::Result<Self> {
+		let size = read.read_u32::<LittleEndian>()?;
+		let unity_note = read.read_u16::<LittleEndian>()?;
+		let fine_tune = read.read_i16::<LittleEndian>()?;
+		let gain = read.read_i32::<LittleEndian>()?;
+		let options = read.read_u32::<LittleEndian>()?;
+		let count = read.read_u32::<LittleEndian>()?;
+		skip(read, (size as u64).saturating_sub(20))?;
+		let mut loops = vec![];
+		for _ in 0..count {
+			let size = read.read_u32::<LittleEndian>()?;
+			loops.push(WaveSampleLoop {
+				kind: read.read_u32::<LittleEndian>()?,
+				start: read.read_u32::<LittleEndian>()?,
+				length: read.read_u32::<LittleEndian>()?,
+			});
+			skip(read, (size as u64).saturating_sub(16))?;
+		}
+		Ok(WaveSample {
+			unity_note: unity_note,
+			fine_tune: fine_tune,
+			gain: gain,
+			options: options,
+			loops: loops,
+		})
+	}
+}
+
+/// `art1` or `art2` chunk.
+#[derive(Clone, Debug)]
+pub struct Articulator {
+	pub fcc: FourCC,
+	pub connections: Vec<ConnectionBlock>,
+}
+
+impl Articulator {
+	pub fn read<R: Read + fmt::Debug>(fcc: FourCC, read: &mut R) -> io::Result<Self> {
+		let size = read.read_u32::<LittleEndian>()?;
+		let count = read.read_u32::<LittleEndian>()?;
+		skip(read, (size as u64).saturating_sub(8))?;
+		let mut connections = vec![];
+		for _ in 0..count {
+			connections.push(ConnectionBlock::deser(read)?);
+		}
+		Ok(Articulator { fcc: fcc, connections: connections })
+	}
+}
+
+/// Reads `art1`/`art2` chunks of `LIST lart`/`LIST lar2`.
+fn read_articulators<'a, T: 'a + Read + Seek + fmt::Debug>(list: &mut riff::List<'a, T>) -> io::Result<Vec<Articulator>> {
+	let mut result = vec![];
+	for item in list.iter() {
+		if let riff::Node::Chunk(mut chunk) = item? {
+			let fcc = chunk.fourcc();
+			if fcc == FCC_ART1 || fcc == FCC_ART2 {
+				result.push(Articulator::read(fcc, &mut chunk.read())?);
+			}
+		}
+	}
+	Ok(result)
+}
+
+/// `ptbl` chunk: offsets of waves relative to start of `wvpl` payload.
+fn read_pool_table<R: Read>(read: &mut R) -> io::Result<Vec<u32>> {
+	let size = read.read_u32::<LittleEndian>()?;
+	let count = read.read_u32::<LittleEndian>()?;
+	skip(read, (size as u64).saturating_sub(8))?;
+	let mut offsets = vec![];
+	for _ in 0..count {
+		offsets.push(read.read_u32::<LittleEndian>()?);
+	}
+	Ok(offsets)
+}
+
+//
+
+#[derive(Clone, Debug)]
+pub struct Region {
+	pub header: RegionHeader,
+	pub sample: Option<WaveSample>,
+	pub link: Option<WaveLink>,
+	pub articulators: Vec<Articulator>,
+}
+
+impl Region {
+	fn from_list<'a, T: 'a + Read + Seek + fmt::Debug>(list: &mut riff::List<'a, T>) -> io::Result<Self> {
+		let mut header = None;
+		let mut sample = None;
+		let mut link = None;
+		let mut articulators = vec![];
+		for item in list.iter() {
+			match item? {
+				riff::Node::Chunk(mut chunk) => {
+					let size = chunk.size();
+					match chunk.fourcc() {
+						FCC_RGNH => header = Some(RegionHeader::read(&mut chunk.read(), size)?),
+						FCC_WSMP => sample = Some(WaveSample::read(&mut chunk.read())?),
+						FCC_WLNK => link = Some(Deser::deser(&mut chunk.read())?),
+						_ => {}
+					}
+				}
+				riff::Node::List(mut sub) => {
+					if sub.fourcc() == FCC_LART || sub.fourcc() == FCC_LAR2 {
+						articulators.extend(read_articulators(&mut sub)?);
+					}
+				}
+			}
+		}
+		Ok(Region {
+			header: header.ok_or_else(|| format_error("Region without rgnh".to_string()))?,
+			sample: sample,
+			link: link,
+			articulators: articulators,
+		})
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct Instrument {
+	pub header: InstrumentHeader,
+	pub name: Option<String>,
+	pub regions: Vec<Region>,
+	/// Global articulation applied to all regions.
+	pub articulators: Vec<Articulator>,
+}
+
+impl Instrument {
+	fn from_list<'a, T: 'a + Read + Seek + fmt::Debug>(list: &mut riff::List<'a, T>) -> io::Result<Self> {
+		let mut header: Option<InstrumentHeader> = None;
+		let mut name = None;
+		let mut regions = vec![];
+		let mut articulators = vec![];
+		for item in list.iter() {
+			match item? {
+				riff::Node::Chunk(mut chunk) => {
+					if chunk.fourcc() == FCC_INSH {
+						header = Some(Deser::deser(&mut chunk.read())?);
+					}
+				}
+				riff::Node::List(mut sub) => match sub.fourcc() {
+					FCC_LRGN => {
+						for item in sub.iter() {
+							if let riff::Node::List(mut region) = item? {
+								if region.fourcc() == FCC_RGN || region.fourcc() == FCC_RGN2 {
+									regions.push(Region::from_list(&mut region)?);
+								}
+							}
+						}
+					}
+					FCC_LART | FCC_LAR2 => articulators.extend(read_articulators(&mut sub)?),
+					FCC_INFO => name = read_name(&mut sub)?,
+					_ => {}
+				},
+			}
+		}
+		let header = header.ok_or_else(|| format_error("Instrument without insh".to_string()))?;
+		if header.regions as usize != regions.len() {
+			return Err(format_error(format!("insh declares {} regions, found {}", { header.regions }, regions.len())));
+		}
+		Ok(Instrument {
+			header: header,
+			name: name,
+			regions: regions,
+			articulators: articulators,
+		})
+	}
+}
+
+/// Entry of wave pool.
+#[derive(Debug)]
+pub struct Wave<'a, T: 'a + Read + Seek + fmt::Debug> {
+	pub format: WaveFormat,
+	pub sample: Option<WaveSample>,
+	pub name: Option<String>,
+	/// Offset of `LIST wave` from start of `wvpl` payload, as used by pool table.
+	pub pool_offset: u64,
+	data: riff::Chunk<'a, T>,
+}
+
+impl<'a, T: 'a + Read + Seek + fmt::Debug> Wave<'a, T> {
+	fn from_list(list: &mut riff::List<'a, T>, pool_offset: u64) -> io::Result<Self> {
+		let mut format = None;
+		let mut sample = None;
+		let mut name = None;
+		let mut data = None;
+		for item in list.iter() {
+			match item? {
+				riff::Node::Chunk(mut chunk) => match chunk.fourcc() {
+					FCC_FMT => format = Some(wav::read_format(&mut chunk)?),
+					FCC_WSMP => sample = Some(WaveSample::read(&mut chunk.read())?),
+					FCC_DATA => data = Some(chunk),
+					_ => {}
+				},
+				riff::Node::List(mut sub) => {
+					if sub.fourcc() == FCC_INFO {
+						name = read_name(&mut sub)?;
+					}
+				}
+			}
+		}
+		Ok(Wave {
+			format: format.ok_or_else(|| format_error("Wave without fmt".to_string()))?,
+			sample: sample,
+			name: name,
+			pool_offset: pool_offset,
+			data: data.ok_or_else(|| format_error("Wave without data".to_string()))?,
+		})
+	}
+
+	pub fn data_size(&self) -> u64 {
+		self.data.size()
+	}
+
+	/// Reader of raw sample data.
+	pub fn data<'b>(&'b mut self) -> riff::ChunkReader<'a, 'b, T> {
+		self.data.read()
+	}
+}
+
+/// Parsed `RIFF DLS ` collection.
+#[derive(Debug)]
+pub struct Collection<'a, T: 'a + Read + Seek + fmt::Debug> {
+	header: CollectionHeader,
+	version: Option<Version>,
+	instruments: Vec<Instrument>,
+	pool_table: Vec<u32>,
+	waves: Vec<Wave<'a, T>>,
+}
+
+impl<'a, T: 'a + Read + Seek + fmt::Debug> Collection<'a, T> {
+	pub fn from_riff(riff: &'a mut riff::Riff<T>) -> io::Result<Self> {
+		let mut form = riff.iter().next().ok_or_else(|| format_error("No RIFF form".to_string()))??;
+		if form.fourcc() != FCC_DLS {
+			return Err(format_error(format!("Expected DLS form, found {}", form.fourcc())));
+		}
+		let mut header: Option<CollectionHeader> = None;
+		let mut version = None;
+		let mut instruments = vec![];
+		let mut pool_table = vec![];
+		let mut waves = vec![];
+		for item in form.iter() {
+			match item? {
+				riff::Node::Chunk(mut chunk) => match chunk.fourcc() {
+					FCC_COLH => header = Some(Deser::deser(&mut chunk.read())?),
+					FCC_VERS => version = Some(Deser::deser(&mut chunk.read())?),
+					FCC_PTBL => pool_table = read_pool_table(&mut chunk.read())?,
+					_ => {}
+				},
+				riff::Node::List(mut list) => match list.fourcc() {
+					FCC_LINS => {
+						for item in list.iter() {
+							if let riff::Node::List(mut ins) = item? {
+								if ins.fourcc() == FCC_INS {
+									instruments.push(Instrument::from_list(&mut ins)?);
+								}
+							}
+						}
+					}
+					FCC_WVPL => {
+						let start = list.data_offset();
+						for item in list.iter() {
+							if let riff::Node::List(mut wave) = item? {
+								if wave.fourcc() == FCC_WAVE {
+									let offset = wave.header_offset() - start;
+									waves.push(Wave::from_list(&mut wave, offset)?);
+								}
+							}
+						}
+					}
+					_ => {}
+				},
+			}
+		}
+		let header = header.ok_or_else(|| format_error("No colh chunk".to_string()))?;
+		if header.instruments as usize != instruments.len() {
+			return Err(format_error(format!("colh declares {} instruments, found {}", { header.instruments }, instruments.len())));
+		}
+		Ok(Collection {
+			header: header,
+			version: version,
+			instruments: instruments,
+			pool_table: pool_table,
+			waves: waves,
+		})
+	}
+
+	pub fn header(&self) -> &CollectionHeader {
+		&self.header
+	}
+
+	pub fn version(&self) -> Option<&Version> {
+		self.version.as_ref()
+	}
+
+	pub fn instruments(&self) -> &[Instrument] {
+		&self.instruments
+	}
+
+	pub fn pool_table(&self) -> &[u32] {
+		&self.pool_table
+	}
+
+	pub fn waves(&self) -> &[Wave<'a, T>] {
+		&self.waves
+	}
+
+	pub fn waves_mut(&mut self) -> &mut [Wave<'a, T>] {
+		&mut self.waves
+	}
+
+	/// Index in `waves` of pool table entry `table_index`, as referenced by `WaveLink`.
+	pub fn wave_index(&self, table_index: u32) -> Option<usize> {
+		let offset = *self.pool_table.get(table_index as usize)? as u64;
+		self.waves.iter().position(|wave| wave.pool_offset == offset)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Cursor, Read};
+
+	use deser::Ser;
+	use riff::{Riff, RiffWriter};
+	use super::*;
+
+	fn u32s(values: &[u32]) -> Vec<u8> {
+		values.iter().flat_map(|&value| vec![value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]).collect()
+	}
+
+	fn ser<S: Ser>(value: S) -> Vec<u8> {
+		let mut data = vec![];
+		value.ser(&mut data).unwrap();
+		data
+	}
+
+	/// Mono 8-bit PCM `fmt ` without `cbSize`.
+	const FMT: &'static [u8] = b"\x01\0\x01\0\x22\x56\0\0\x22\x56\0\0\x01\0\x08\0";
+
+	fn file(table: &[u32]) -> Vec<u8> {
+		let mut writer = RiffWriter::new(Cursor::new(vec![]));
+		writer.begin_riff(FCC_DLS).unwrap();
+		writer.write_chunk(FCC_COLH, &ser(CollectionHeader { instruments: 1 })).unwrap();
+		writer.write_chunk(FCC_VERS, &ser(Version { ms: 0x00010002, ls: 0 })).unwrap();
+		let mut ptbl = u32s(&[8, table.len() as u32]);
+		ptbl.extend_from_slice(&u32s(table));
+		writer.write_chunk(FCC_PTBL, &ptbl).unwrap();
+
+		writer.begin_list(FCC_LINS).unwrap();
+		writer.begin_list(FCC_INS).unwrap();
+		let locale = Locale { bank: F_INSTRUMENT_DRUMS, instrument: 5 };
+		writer.write_chunk(FCC_INSH, &ser(InstrumentHeader { regions: 1, locale: locale })).unwrap();
+		writer.begin_list(FCC_LRGN).unwrap();
+		writer.begin_list(FCC_RGN).unwrap();
+		writer.write_chunk(FCC_RGNH, b"\x24\0\x30\0\0\0\x7f\0\x01\0\0\0").unwrap();
+		let mut wsmp = u32s(&[20]);
+		wsmp.extend_from_slice(b"\x3c\0\xf6\xff");
+		wsmp.extend_from_slice(&u32s(&[0, 0, 1, 16, 0, 100, 50]));
+		writer.write_chunk(FCC_WSMP, &wsmp).unwrap();
+		writer.write_chunk(FCC_WLNK, &ser(WaveLink { options: 0, phase_group: 0, channel: 1, table_index: 1 })).unwrap();
+		writer.end().unwrap();
+		writer.end().unwrap();
+		writer.begin_list(FCC_LART).unwrap();
+		let mut art1 = u32s(&[8, 1]);
+		art1.extend_from_slice(&ser(ConnectionBlock { source: 0, control: 0, destination: 3, transform: 0, scale: -100 }));
+		writer.write_chunk(FCC_ART1, &art1).unwrap();
+		writer.end().unwrap();
+		writer.begin_list(FCC_INFO).unwrap();
+		writer.write_chunk(FCC_INAM, b"Kit\0").unwrap();
+		writer.end().unwrap();
+		writer.end().unwrap();
+		writer.end().unwrap();
+
+		writer.begin_list(FCC_WVPL).unwrap();
+		for (index, name) in ["one", "two"].iter().enumerate() {
+			writer.begin_list(FCC_WAVE).unwrap();
+			writer.write_chunk(FCC_FMT, FMT).unwrap();
+			writer.write_chunk(FCC_DATA, &[index as u8; 4]).unwrap();
+			if index == 1 {
+				writer.begin_list(FCC_INFO).unwrap();
+				writer.write_chunk(FCC_INAM, name.as_bytes()).unwrap();
+				writer.end().unwrap();
+			}
+			writer.end().unwrap();
+		}
+		writer.end().unwrap();
+		writer.end().unwrap();
+		writer.finish().unwrap().into_inner()
+	}
+
+	#[test]
+	fn collection() {
+		// First wave takes 12 + 24 + 12 bytes.
+		let mut riff = Riff::new(Cursor::new(file(&[0, 48]))).unwrap();
+		let mut dls = Collection::from_riff(&mut riff).unwrap();
+		assert_eq!({ dls.version().unwrap().ms }, 0x00010002);
+		assert_eq!(dls.instruments().len(), 1);
+		let instrument = dls.instruments()[0].clone();
+		assert_eq!(instrument.name, Some("Kit".to_string()));
+		assert_eq!({ instrument.header.locale.bank } & F_INSTRUMENT_DRUMS, F_INSTRUMENT_DRUMS);
+		assert_eq!(instrument.articulators.len(), 1);
+		assert_eq!({ instrument.articulators[0].connections[0].scale }, -100);
+		let region = &instrument.regions[0];
+		assert_eq!((region.header.key_low, region.header.key_high, region.header.layer), (0x24, 0x30, None));
+		let sample = region.sample.clone().unwrap();
+		assert_eq!((sample.unity_note, sample.fine_tune), (60, -10));
+		assert_eq!(sample.loops, vec![WaveSampleLoop { kind: 0, start: 100, length: 50 }]);
+
+		assert_eq!(dls.pool_table(), &[0, 48]);
+		let table_index = { region.link.unwrap().table_index };
+		let index = dls.wave_index(table_index).unwrap();
+		assert_eq!(index, 1);
+		let wave = &mut dls.waves_mut()[index];
+		assert_eq!(wave.name, Some("two".to_string()));
+		assert_eq!({ wave.format.header().samples_per_sec }, 22050);
+		let mut data = vec![];
+		wave.data().read_to_end(&mut data).unwrap();
+		assert_eq!(data, vec![1; 4]);
+	}
+
+	#[test]
+	fn huge_pool_table_count() {
+		let mut ptbl = u32s(&[8, 0xFFFFFFFF]);
+		ptbl.extend_from_slice(&u32s(&[0]));
+		assert!(read_pool_table(&mut &ptbl[..]).is_err());
+	}
+}
@@ -1,9 +1,10 @@
 use std::fmt::{self, Formatter, Display, Debug};
 use std::str::from_utf8;
-use std::{io, mem};
+use std::io::{self, Read, Write};
 
-use deser::PlainOldData;
+use deser::{Deser, Ser, Endian};
 
+#[repr(transparent)]
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FourCC(pub [u8; 4]);
 
@@ -12,7 +13,8 @@ impl FourCC {
         FourCC(arr)
     }
     pub fn from_ref<'a>(arr: &'a [u8; 4]) -> &'a Self {
-        unsafe { mem::transmute(arr) }
+        // Sound because FourCC is `repr(transparent)` over `[u8; 4]`.
+        unsafe { &*(arr as *const [u8; 4] as *const FourCC) }
     }
     pub fn from_slice(bytes: &[u8]) -> Option<Self> {
         if bytes.len() != 4 {
@@ -44,4 +46,22 @@ impl Debug for FourCC {
     }
 }
 
-unsafe impl PlainOldData for FourCC {}
+impl Deser for FourCC {
+    fn deser<R: Read+::std::fmt::Debug>(read: &mut R) -> io::Result<FourCC> {
+        let mut bytes = [0; 4];
+        read.read_exact(&mut bytes)?;
+        Ok(FourCC(bytes))
+    }
+    fn deser_endian<R: Read+::std::fmt::Debug>(read: &mut R, _: Endian) -> io::Result<FourCC> {
+        Self::deser(read)
+    }
+}
+
+impl Ser for FourCC {
+    fn ser<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_all(&self.0)
+    }
+    fn ser_endian<W: Write>(&self, write: &mut W, _: Endian) -> io::Result<()> {
+        self.ser(write)
+    }
+}
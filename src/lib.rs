@@ -12,10 +12,20 @@ macro_rules! inspect_err {
 }
 
 extern crate byteorder;
+extern crate memmap;
+#[macro_use]
+extern crate avi_derive;
 
 pub mod riff;
 pub mod fourcc;
 pub mod demuxer;
+pub mod wav;
+pub mod webp;
+pub mod sf2;
+pub mod ani;
+pub mod dls;
+pub mod pal;
+pub mod probe;
+pub mod deser;
 
-mod deser;
 mod data;
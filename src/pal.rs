@@ -0,0 +1,125 @@
+use std::io::{self, Read, Write, Seek};
+use std::fmt;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+
+use riff;
+use data::FCC_DATA;
+use deser::{Deser, Ser};
+use fourcc::FourCC;
+
+pub use data::{Palette, PaletteEntry, PALETTEENTRY, RGBQUAD, BitmapInfo};
+
+pub const FCC_PAL: FourCC = FourCC([b'P', b'A', b'L', b' ']);
+
+/// `palVersion` of `LOGPALETTE`.
+pub const PAL_VERSION: u16 = 0x300;
+
+fn format_error(message: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// `RIFF PAL ` file: `LOGPALETTE` in `data` chunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PalFile {
+	pub version: u16,
+	pub palette: Palette,
+}
+
+impl PalFile {
+	pub fn new(palette: Palette) -> Self {
+		PalFile {
+			version: PAL_VERSION,
+			palette: palette,
+		}
+	}
+
+	pub fn from_riff<T: Read + Seek + fmt::Debug>(riff: &mut riff::Riff<T>) -> io::Result<Self> {
+		let mut form = riff.iter().next().ok_or_else(|| format_error("No RIFF form".to_string()))??;
+		if form.fourcc() != FCC_PAL {
+			return Err(format_error(format!("Expected PAL form, found {}", form.fourcc())));
+		}
+		for item in form.iter() {
+			if let riff::Node::Chunk(mut chunk) = item? {
+				if chunk.fourcc() == FCC_DATA {
+					return Self::read_data(&mut chunk.read());
+				}
+			}
+		}
+		Err(format_error("No data chunk".to_string()))
+	}
+
+	/// Reads `LOGPALETTE` payload of `data` chunk.
+	pub fn read_data<R: Read + fmt::Debug>(read: &mut R) -> io::Result<Self> {
+		let version = read.read_u16::<LittleEndian>()?;
+		let count = read.read_u16::<LittleEndian>()?;
+		let mut entries = vec![];
+		for _ in 0..count {
+			entries.push(PaletteEntry::deser(read)?);
+		}
+		Ok(PalFile {
+			version: version,
+			palette: Palette { entries: entries },
+		})
+	}
+
+	/// Writes `LOGPALETTE` payload of `data` chunk.
+	pub fn write_data<W: Write>(&self, write: &mut W) -> io::Result<()> {
+		if self.palette.len() > u16::max_value() as usize {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Too many palette entries: {}", self.palette.len())));
+		}
+		write.write_u16::<LittleEndian>(self.version)?;
+		write.write_u16::<LittleEndian>(self.palette.len() as u16)?;
+		self.palette.entries.ser(write)
+	}
+
+	/// Writes complete `RIFF PAL ` file.
+	pub fn write<W: Write + Seek>(&self, write: W) -> io::Result<W> {
+		let mut writer = riff::RiffWriter::new(write);
+		writer.begin_riff(FCC_PAL)?;
+		writer.begin_chunk(FCC_DATA)?;
+		self.write_data(&mut writer)?;
+		writer.end()?;
+		writer.end()?;
+		writer.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use riff::Riff;
+	use super::{PalFile, Palette, PaletteEntry, PAL_VERSION};
+
+	fn palette() -> Palette {
+		Palette {
+			entries: vec![
+				PaletteEntry { red: 1, green: 2, blue: 3, flags: 0 },
+				PaletteEntry { red: 4, green: 5, blue: 6, flags: 1 },
+			],
+		}
+	}
+
+	#[test]
+	fn file_layout() {
+		let data = PalFile::new(palette()).write(Cursor::new(vec![])).unwrap().into_inner();
+		assert_eq!(&data[..], &b"RIFF\x18\0\0\0PAL data\x0c\0\0\0\0\x03\x02\0\x01\x02\x03\0\x04\x05\x06\x01"[..]);
+	}
+
+	#[test]
+	fn round_trip() {
+		let data = PalFile::new(palette()).write(Cursor::new(vec![])).unwrap().into_inner();
+		let mut riff = Riff::new(Cursor::new(data)).unwrap();
+		let pal = PalFile::from_riff(&mut riff).unwrap();
+		assert_eq!(pal, PalFile { version: PAL_VERSION, palette: palette() });
+	}
+
+	#[test]
+	fn rgbquads_drop_flags() {
+		let quads = palette().to_rgbquads();
+		let back = Palette::from_rgbquads(&quads);
+		assert_eq!(back.entries[1], PaletteEntry { red: 4, green: 5, blue: 6, flags: 0 });
+		assert_eq!((quads[0].blue, quads[0].red), (3, 1));
+	}
+}
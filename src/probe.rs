@@ -0,0 +1,173 @@
+use std::io::{self, Read, Seek};
+use std::fmt;
+
+use riff::{self, Endian};
+use fourcc::FourCC;
+use data::{FCC_AVI, FCC_WAVE};
+use webp::{self, FCC_WEBP};
+use sf2::{self, FCC_SFBK};
+use ani::{self, FCC_ACON};
+use dls::{self, FCC_DLS};
+use pal::{self, FCC_PAL};
+use demuxer;
+use wav;
+
+/// MIDI file wrapped in RIFF.
+pub const FCC_RMID: FourCC = FourCC([b'R', b'M', b'I', b'D']);
+/// CD-ROM XA sectors.
+pub const FCC_CDXA: FourCC = FourCC([b'C', b'D', b'X', b'A']);
+
+/// Known form types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormType {
+	Avi,
+	Wave,
+	WebP,
+	SoundFont,
+	AnimatedCursor,
+	Dls,
+	Palette,
+	Rmid,
+	Cdxa,
+	Unknown(FourCC),
+}
+
+impl FormType {
+	pub fn from_fourcc(fcc: FourCC) -> Self {
+		match fcc {
+			FCC_AVI => FormType::Avi,
+			FCC_WAVE => FormType::Wave,
+			FCC_WEBP => FormType::WebP,
+			FCC_SFBK => FormType::SoundFont,
+			FCC_ACON => FormType::AnimatedCursor,
+			FCC_DLS => FormType::Dls,
+			FCC_PAL => FormType::Palette,
+			FCC_RMID => FormType::Rmid,
+			FCC_CDXA => FormType::Cdxa,
+			fcc => FormType::Unknown(fcc),
+		}
+	}
+}
+
+/// What the first form header tells about a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Probe {
+	pub form: FormType,
+	/// Raw form type.
+	pub fcc: FourCC,
+	pub endian: Endian,
+	/// Form is `RF64`/`BW64` with sizes in `ds64`.
+	pub rf64: bool,
+}
+
+/// Reads first 12 bytes of `read` and identifies the form.
+pub fn probe<R: Read>(read: &mut R) -> io::Result<Probe> {
+	let mut head = [0; 12];
+	read.read_exact(&mut head)?;
+	let id = FourCC::from_slice(&head[..4]).unwrap();
+	let (endian, rf64) = match id {
+		riff::RIFF => (Endian::Little, false),
+		riff::RIFX => (Endian::Big, false),
+		riff::RF64 | riff::BW64 => (Endian::Little, true),
+		id => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Not a RIFF file: {}", id))),
+	};
+	let fcc = FourCC::from_slice(&head[8..]).unwrap();
+	Ok(Probe {
+		form: FormType::from_fourcc(fcc),
+		fcc: fcc,
+		endian: endian,
+		rf64: rf64,
+	})
+}
+
+/// Identifies first form of already opened file. Only the form header is read,
+/// so truncated files and forms with damaged `ds64` are still identified.
+pub fn probe_riff<T: Read + Seek + fmt::Debug>(riff: &riff::Riff<T>) -> io::Result<Probe> {
+	let mut head = [0; 12];
+	riff.read_exact_at(0, &mut head)?;
+	probe(&mut &head[..])
+}
+
+/// High level parser matching form type.
+#[derive(Debug)]
+pub enum Parsed<'a, T: 'a + Read + Seek + fmt::Debug> {
+	Avi(demuxer::Demuxer<'a, T>),
+	Wave(wav::Wav<'a, T>),
+	WebP(webp::WebP<'a, T>),
+	SoundFont(sf2::SoundFont<'a, T>),
+	AnimatedCursor(ani::AnimatedCursor<'a, T>),
+	Dls(dls::Collection<'a, T>),
+	Palette(pal::PalFile),
+	/// Known or unknown form without parser in this crate.
+	Unsupported(Probe),
+}
+
+/// Probes `riff` and opens parser for its form type.
+pub fn open<'a, T: 'a + Read + Seek + fmt::Debug>(riff: &'a mut riff::Riff<T>) -> io::Result<Parsed<'a, T>> {
+	let probe = probe_riff(riff)?;
+	Ok(match probe.form {
+		FormType::Avi => Parsed::Avi(demuxer::Demuxer::from_riff(riff)?),
+		FormType::Wave => Parsed::Wave(wav::Wav::from_riff(riff)?),
+		FormType::WebP => Parsed::WebP(webp::WebP::from_riff(riff)?),
+		FormType::SoundFont => Parsed::SoundFont(sf2::SoundFont::from_riff(riff)?),
+		FormType::AnimatedCursor => Parsed::AnimatedCursor(ani::AnimatedCursor::from_riff(riff)?),
+		FormType::Dls => Parsed::Dls(dls::Collection::from_riff(riff)?),
+		FormType::Palette => Parsed::Palette(pal::PalFile::from_riff(riff)?),
+		_ => Parsed::Unsupported(probe),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Cursor, ErrorKind};
+
+	use riff::{Riff, Endian};
+	use fourcc::FourCC;
+	use super::{probe, probe_riff, open, FormType, Parsed, FCC_RMID};
+
+	#[test]
+	fn form_types() {
+		let cases: &[(&[u8], FormType, Endian, bool)] = &[
+			(b"RIFF\x04\0\0\0AVI ", FormType::Avi, Endian::Little, false),
+			(b"RIFX\0\0\0\x04WAVE", FormType::Wave, Endian::Big, false),
+			(b"RF64\xff\xff\xff\xffWAVE", FormType::Wave, Endian::Little, true),
+			(b"RIFF\x04\0\0\0RMID", FormType::Rmid, Endian::Little, false),
+			(b"RIFF\x04\0\0\0abcd", FormType::Unknown(FourCC::from_str("abcd").unwrap()), Endian::Little, false),
+		];
+		for &(data, form, endian, rf64) in cases {
+			let probe = probe(&mut &data[..]).unwrap();
+			assert_eq!((probe.form, probe.endian, probe.rf64), (form, endian, rf64));
+		}
+		assert_eq!(probe(&mut &b"RIFF\x04\0\0\0RMID"[..]).unwrap().fcc, FCC_RMID);
+	}
+
+	#[test]
+	fn not_riff() {
+		assert_eq!(probe(&mut &b"LIST\x04\0\0\0INFO"[..]).unwrap_err().kind(), ErrorKind::InvalidData);
+		assert_eq!(probe(&mut &b"RIFF\x04\0"[..]).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+	}
+
+	#[test]
+	fn truncated_file() {
+		// Form claims far more data than present.
+		let riff = Riff::new(Cursor::new(b"RIFF\0\0\x10\0WAVEfmt ".to_vec())).unwrap();
+		let probe = probe_riff(&riff).unwrap();
+		assert_eq!(probe.form, FormType::Wave);
+	}
+
+	#[test]
+	fn rf64_without_ds64() {
+		let riff = Riff::new(Cursor::new(b"RF64\xff\xff\xff\xffWAVE".to_vec())).unwrap();
+		let probe = probe_riff(&riff).unwrap();
+		assert_eq!((probe.form, probe.rf64), (FormType::Wave, true));
+	}
+
+	#[test]
+	fn open_unsupported() {
+		let mut riff = Riff::new(Cursor::new(b"RIFF\x04\0\0\0RMID".to_vec())).unwrap();
+		match open(&mut riff).unwrap() {
+			Parsed::Unsupported(probe) => assert_eq!(probe.form, FormType::Rmid),
+			parsed => panic!("{:?}", parsed),
+		};
+	}
+}
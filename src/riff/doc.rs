@@ -0,0 +1,481 @@
+use std::io::{self, Read, Write};
+use std::mem;
+
+use byteorder::{WriteBytesExt, LittleEndian, BigEndian};
+
+use fourcc::FourCC;
+use deser::Deser;
+
+use super::{Endian, Ds64, FormHeader, LIST, RIFF, RIFX, RF64, BW64, DS64, SIZE_PLACEHOLDER};
+use super::{list_payload_size, resolve_size, read_ds64, chunk_too_big, list_too_big};
+use super::rf64::DATA;
+
+/// Owned RIFF chunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DocChunk {
+    pub fcc: FourCC,
+    pub data: Vec<u8>,
+    /// Padding byte written after odd-sized payload. `None` only if file ended
+    /// right after odd-sized payload, zero for nodes which had even size.
+    pad: Option<u8>,
+    /// Size field held `RF64` placeholder.
+    placeholder: bool,
+}
+
+impl DocChunk {
+    pub fn new(fcc: FourCC, data: Vec<u8>) -> Self {
+        DocChunk {
+            fcc: fcc,
+            data: data,
+            pad: Some(0),
+            placeholder: false,
+        }
+    }
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+}
+
+/// Owned `LIST` or top level form.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DocList {
+    /// `LIST`, `RIFF`, `RIFX`, `RF64` or `BW64`.
+    pub id: FourCC,
+    /// List or form type.
+    pub fcc: FourCC,
+    pub children: Vec<DocNode>,
+    /// Bytes after last child too short to hold a header.
+    trailing: Vec<u8>,
+    pad: Option<u8>,
+    placeholder: bool,
+}
+
+impl DocList {
+    /// New `LIST` with given list type.
+    pub fn new(fcc: FourCC) -> Self {
+        Self::with_id(LIST, fcc)
+    }
+    /// New `RIFF` form with given form type.
+    pub fn form(fcc: FourCC) -> Self {
+        Self::with_id(RIFF, fcc)
+    }
+    fn with_id(id: FourCC, fcc: FourCC) -> Self {
+        DocList {
+            id: id,
+            fcc: fcc,
+            children: vec![],
+            trailing: vec![],
+            pad: Some(0),
+            placeholder: false,
+        }
+    }
+
+    /// Size of payload after list type, as written into size field minus 4.
+    pub fn size(&self) -> u64 {
+        self.children.iter().map(DocNode::footprint).sum::<u64>() + self.trailing.len() as u64
+    }
+
+    pub fn push(&mut self, node: DocNode) {
+        self.children.push(node);
+    }
+    pub fn insert(&mut self, index: usize, node: DocNode) {
+        self.children.insert(index, node);
+    }
+    pub fn remove(&mut self, index: usize) -> DocNode {
+        self.children.remove(index)
+    }
+    /// Replace child at `index`, returning old one.
+    pub fn replace(&mut self, index: usize, node: DocNode) -> DocNode {
+        mem::replace(&mut self.children[index], node)
+    }
+    /// Index of first child with given FourCC (list type for lists).
+    pub fn position(&self, fcc: FourCC) -> Option<usize> {
+        self.children.iter().position(|node| node.fourcc() == fcc)
+    }
+    pub fn find(&self, fcc: FourCC) -> Option<&DocNode> {
+        self.children.iter().find(|node| node.fourcc() == fcc)
+    }
+    pub fn find_mut(&mut self, fcc: FourCC) -> Option<&mut DocNode> {
+        self.children.iter_mut().find(|node| node.fourcc() == fcc)
+    }
+
+    fn endian(&self) -> Endian {
+        if self.id == RIFX { Endian::Big } else { Endian::Little }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DocNode {
+    List(DocList),
+    Chunk(DocChunk),
+}
+
+impl DocNode {
+    pub fn fourcc(&self) -> FourCC {
+        match *self {
+            DocNode::List(ref item) => item.fcc,
+            DocNode::Chunk(ref item) => item.fcc,
+        }
+    }
+    pub fn as_list(&self) -> Option<&DocList> {
+        match *self {
+            DocNode::List(ref list) => Some(list),
+            DocNode::Chunk(_) => None,
+        }
+    }
+    pub fn as_list_mut(&mut self) -> Option<&mut DocList> {
+        match *self {
+            DocNode::List(ref mut list) => Some(list),
+            DocNode::Chunk(_) => None,
+        }
+    }
+    pub fn as_chunk(&self) -> Option<&DocChunk> {
+        match *self {
+            DocNode::Chunk(ref chunk) => Some(chunk),
+            DocNode::List(_) => None,
+        }
+    }
+    pub fn as_chunk_mut(&mut self) -> Option<&mut DocChunk> {
+        match *self {
+            DocNode::Chunk(ref mut chunk) => Some(chunk),
+            DocNode::List(_) => None,
+        }
+    }
+
+    /// Value of size field.
+    fn size(&self) -> u64 {
+        match *self {
+            DocNode::List(ref list) => mem::size_of::<FourCC>() as u64 + list.size(),
+            DocNode::Chunk(ref chunk) => chunk.size(),
+        }
+    }
+    fn pad(&self) -> Option<u8> {
+        match *self {
+            DocNode::List(ref list) => list.pad,
+            DocNode::Chunk(ref chunk) => chunk.pad,
+        }
+    }
+    /// Bytes taken by the node including header and padding.
+    fn footprint(&self) -> u64 {
+        let size = self.size();
+        let pad = if size % 2 == 1 && self.pad().is_some() { 1 } else { 0 };
+        mem::size_of::<(FourCC, u32)>() as u64 + size + pad
+    }
+}
+
+impl From<DocList> for DocNode {
+    fn from(list: DocList) -> Self {
+        DocNode::List(list)
+    }
+}
+
+impl From<DocChunk> for DocNode {
+    fn from(chunk: DocChunk) -> Self {
+        DocNode::Chunk(chunk)
+    }
+}
+
+/// Owned RIFF file which can be edited and written back.
+///
+/// Padding bytes, trailing bytes and `RF64` placeholders are preserved,
+/// so unmodified document is written byte-identical.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RiffDocument {
+    pub forms: Vec<DocList>,
+    /// Bytes after last form.
+    trailing: Vec<u8>,
+}
+
+impl RiffDocument {
+    pub fn new() -> Self {
+        RiffDocument::default()
+    }
+
+    pub fn read<R: Read>(read: &mut R) -> io::Result<Self> {
+        let mut data = vec![];
+        read.read_to_end(&mut data)?;
+        Self::from_bytes(&data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let mut rest = data;
+        let mut forms = vec![];
+        while let Some(header) = FormHeader::parse(rest) {
+            let body = &rest[12..];
+            let ds64 = if header.is_rf64() {
+                Some(read_ds64(&mut &body[..])?)
+            } else {
+                None
+            };
+            let size = header.payload_size(ds64.as_ref())?;
+            let body = take(body, size).ok_or_else(|| list_too_big(size, body.len() as u64))?;
+            let (children, trailing) = parse_children(body, header.endian, ds64.as_ref())?;
+            rest = &rest[12 + size as usize..];
+            let pad = take_pad(&mut rest, size);
+            forms.push(DocList {
+                id: header.id,
+                fcc: header.fcc,
+                children: children,
+                trailing: trailing,
+                pad: pad,
+                placeholder: header.size == SIZE_PLACEHOLDER,
+            });
+        }
+        Ok(RiffDocument {
+            forms: forms,
+            trailing: rest.to_vec(),
+        })
+    }
+
+    pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        for form in &self.forms {
+            let endian = form.endian();
+            let ds64 = if form.id == RF64 || form.id == BW64 { Some(form) } else { None };
+            write_list(write, form, endian, ds64)?;
+        }
+        write.write_all(&self.trailing)
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+        self.write(&mut data)?;
+        Ok(data)
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn take(data: &[u8], size: u64) -> Option<&[u8]> {
+    if size > data.len() as u64 {
+        None
+    } else {
+        Some(&data[..size as usize])
+    }
+}
+
+/// Padding byte after payload of `size`. Nodes of even size get zero, used if they become odd.
+fn take_pad(rest: &mut &[u8], size: u64) -> Option<u8> {
+    if size % 2 == 0 {
+        return Some(0);
+    }
+    let (&pad, tail) = rest.split_first()?;
+    *rest = tail;
+    Some(pad)
+}
+
+fn parse_children(mut rest: &[u8], endian: Endian, ds64: Option<&Ds64>) -> io::Result<(Vec<DocNode>, Vec<u8>)> {
+    let mut children = vec![];
+    while rest.len() >= mem::size_of::<(FourCC, u32)>() {
+        let fcc = FourCC::from_slice(&rest[..4]).unwrap();
+        let raw = endian.read_u32(&mut &rest[4..8])?;
+        let size = resolve_size(fcc, raw, ds64)?;
+        if fcc == LIST {
+            if rest.len() < mem::size_of::<(FourCC, u32, FourCC)>() {
+                break;
+            }
+            let list_fcc = FourCC::from_slice(&rest[8..12]).unwrap();
+            let size = list_payload_size(size)?;
+            let body = take(&rest[12..], size).ok_or_else(|| list_too_big(size, rest.len() as u64 - 12))?;
+            let (sub, trailing) = parse_children(body, endian, ds64)?;
+            rest = &rest[12 + size as usize..];
+            let pad = take_pad(&mut rest, size);
+            children.push(DocNode::List(DocList {
+                id: LIST,
+                fcc: list_fcc,
+                children: sub,
+                trailing: trailing,
+                pad: pad,
+                placeholder: raw == SIZE_PLACEHOLDER,
+            }));
+        } else {
+            let data = take(&rest[8..], size).ok_or_else(|| chunk_too_big(size, rest.len() as u64 - 8))?.to_vec();
+            rest = &rest[8 + size as usize..];
+            let pad = take_pad(&mut rest, size);
+            children.push(DocNode::Chunk(DocChunk {
+                fcc: fcc,
+                data: data,
+                pad: pad,
+                placeholder: raw == SIZE_PLACEHOLDER,
+            }));
+        }
+    }
+    Ok((children, rest.to_vec()))
+}
+
+fn write_size<W: Write>(write: &mut W, size: u64, placeholder: bool, endian: Endian) -> io::Result<()> {
+    let size = if placeholder {
+        SIZE_PLACEHOLDER
+    } else if size >= SIZE_PLACEHOLDER as u64 {
+        return Err(invalid(format!("Size doesn't fit 32-bit field: {}", size)));
+    } else {
+        size as u32
+    };
+    match endian {
+        Endian::Little => write.write_u32::<LittleEndian>(size),
+        Endian::Big => write.write_u32::<BigEndian>(size),
+    }
+}
+
+/// Padding is skipped only where file lacked it at its end.
+fn write_pad<W: Write>(write: &mut W, size: u64, pad: Option<u8>) -> io::Result<()> {
+    match pad {
+        Some(pad) if size % 2 == 1 => write.write_all(&[pad]),
+        _ => Ok(()),
+    }
+}
+
+/// Write list. `rf64` is the enclosing `RF64` form, its `ds64` chunk gets actual sizes.
+fn write_list<W: Write>(write: &mut W, list: &DocList, endian: Endian, rf64: Option<&DocList>) -> io::Result<()> {
+    let size = mem::size_of::<FourCC>() as u64 + list.size();
+    write.write_all(&list.id.0)?;
+    write_size(write, size, list.placeholder, endian)?;
+    write.write_all(&list.fcc.0)?;
+    for child in &list.children {
+        match *child {
+            DocNode::List(ref sub) => write_list(write, sub, endian, rf64)?,
+            DocNode::Chunk(ref chunk) => {
+                write.write_all(&chunk.fcc.0)?;
+                write_size(write, chunk.size(), chunk.placeholder, endian)?;
+                match rf64 {
+                    Some(form) if chunk.fcc == DS64 => write.write_all(&updated_ds64(form, &chunk.data))?,
+                    _ => write.write_all(&chunk.data)?,
+                }
+                write_pad(write, chunk.size(), chunk.pad)?;
+            }
+        }
+    }
+    write.write_all(&list.trailing)?;
+    write_pad(write, size, list.pad)
+}
+
+/// `ds64` payload with sizes of form and placeholder-sized chunks updated.
+fn updated_ds64(form: &DocList, data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    let ds64 = match Ds64::deser(&mut &data[..]) {
+        Ok(ds64) => ds64,
+        Err(_) => return data,
+    };
+    let mut put = |at: usize, value: u64| {
+        if data.len() >= at + 8 {
+            (&mut data[at..at + 8]).write_u64::<LittleEndian>(value).unwrap();
+        }
+    };
+    if form.placeholder {
+        put(0, mem::size_of::<FourCC>() as u64 + form.size());
+    }
+    let placeholder = |fcc: FourCC| {
+        form.children.iter().filter_map(DocNode::as_chunk).find(|chunk| chunk.placeholder && chunk.fcc == fcc)
+    };
+    if let Some(chunk) = placeholder(DATA) {
+        put(8, chunk.size());
+    }
+    for (index, &(fcc, _)) in ds64.table.iter().enumerate() {
+        if let Some(chunk) = placeholder(fcc) {
+            put(28 + index * 12 + 4, chunk.size());
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{WriteBytesExt, LittleEndian};
+
+    use deser::Deser;
+    use fourcc::FourCC;
+    use riff::{Ds64, RF64, DS64, SIZE_PLACEHOLDER};
+    use super::{RiffDocument, DocNode, DocChunk};
+
+    const ABCD: FourCC = FourCC([b'a', b'b', b'c', b'd']);
+    const BIG1: FourCC = FourCC([b'b', b'i', b'g', b'1']);
+    const BIG2: FourCC = FourCC([b'b', b'i', b'g', b'2']);
+
+    /// Odd chunk with pad, nested list, bytes too short for a header and a second form.
+    const FILE: &'static [u8] = b"RIFF\x28\0\0\0TEST\
+                                  abcd\x03\0\0\0xyz\x07\
+                                  LIST\x10\0\0\0INFOefgh\x02\0\0\0uv\x01\x02\
+                                  RIFF\x04\0\0\0NEXT\
+                                  tail";
+
+    #[test]
+    fn round_trip() {
+        let doc = RiffDocument::from_bytes(FILE).unwrap();
+        assert_eq!(doc.forms.len(), 2);
+        assert_eq!(doc.forms[0].children.len(), 2);
+        assert_eq!(doc.to_bytes().unwrap(), FILE);
+    }
+
+    #[test]
+    fn missing_pad_at_end() {
+        let file = b"RIFF\x0f\0\0\0TESTabcd\x03\0\0\0xyz";
+        let doc = RiffDocument::from_bytes(file).unwrap();
+        assert_eq!(doc.to_bytes().unwrap(), &file[..]);
+    }
+
+    #[test]
+    fn odd_edit_adds_pad() {
+        let mut doc = RiffDocument::from_bytes(FILE).unwrap();
+        {
+            let list = doc.forms[0].find_mut(FourCC::from_str("INFO").unwrap()).unwrap().as_list_mut().unwrap();
+            list.children[0].as_chunk_mut().unwrap().data = b"odd".to_vec();
+        }
+        let data = doc.to_bytes().unwrap();
+        assert_eq!(&data[36..48], b"efgh\x03\0\0\0odd\0");
+        let reparsed = RiffDocument::from_bytes(&data).unwrap();
+        assert_eq!(reparsed, doc);
+        assert_eq!(reparsed.forms[1].fcc, FourCC::from_str("NEXT").unwrap());
+        match reparsed.forms[0].children[1] {
+            DocNode::List(ref list) => assert_eq!(list.size(), 14),
+            _ => panic!("expected list"),
+        }
+    }
+
+    fn rf64(table: &[(FourCC, u64)], chunks: &[(FourCC, &[u8])]) -> Vec<u8> {
+        let mut ds64 = vec![];
+        ds64.write_u64::<LittleEndian>(0).unwrap();
+        ds64.write_u64::<LittleEndian>(0).unwrap();
+        ds64.write_u64::<LittleEndian>(0).unwrap();
+        ds64.write_u32::<LittleEndian>(table.len() as u32).unwrap();
+        for &(fcc, size) in table {
+            ds64.extend_from_slice(&fcc.0);
+            ds64.write_u64::<LittleEndian>(size).unwrap();
+        }
+        let mut body = vec![];
+        body.extend_from_slice(b"TEST");
+        body.extend_from_slice(&DS64.0);
+        body.write_u32::<LittleEndian>(ds64.len() as u32).unwrap();
+        body.extend_from_slice(&ds64);
+        for &(fcc, data) in chunks {
+            body.extend_from_slice(&fcc.0);
+            body.write_u32::<LittleEndian>(SIZE_PLACEHOLDER).unwrap();
+            body.extend_from_slice(data);
+        }
+        let size = body.len() as u64;
+        (&mut body[12..20]).write_u64::<LittleEndian>(size).unwrap();
+        let mut file = vec![];
+        file.extend_from_slice(&RF64.0);
+        file.write_u32::<LittleEndian>(SIZE_PLACEHOLDER).unwrap();
+        file.extend_from_slice(&body);
+        file
+    }
+
+    #[test]
+    fn ds64_table_follows_edits() {
+        // Table order differs from chunk order.
+        let file = rf64(&[(BIG1, 2), (BIG2, 4)], &[(BIG2, b"2222"), (BIG1, b"11")]);
+        let mut doc = RiffDocument::from_bytes(&file).unwrap();
+        assert_eq!(doc.to_bytes().unwrap(), file);
+        doc.forms[0].find_mut(BIG1).unwrap().as_chunk_mut().unwrap().data = b"111111".to_vec();
+        doc.forms[0].find_mut(BIG2).unwrap().as_chunk_mut().unwrap().data = b"22".to_vec();
+        doc.forms[0].push(DocNode::Chunk(DocChunk::new(ABCD, b"x".to_vec())));
+        let data = doc.to_bytes().unwrap();
+        let ds64 = Ds64::deser(&mut &data[20..]).unwrap();
+        assert_eq!(ds64.riff_size, data.len() as u64 - 8);
+        assert_eq!(ds64.table, vec![(BIG1, 6), (BIG2, 2)]);
+        let reparsed = RiffDocument::from_bytes(&data).unwrap();
+        assert_eq!(reparsed.forms[0].children.len(), 4);
+        assert_eq!(reparsed.forms[0].find(ABCD).unwrap().as_chunk().unwrap().data, b"x");
+    }
+}
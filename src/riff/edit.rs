@@ -0,0 +1,451 @@
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Read, Write, Seek, SeekFrom};
+use std::cmp;
+
+use byteorder::{WriteBytesExt, LittleEndian, BigEndian};
+
+use fourcc::FourCC;
+use deser::Deser;
+
+use super::{Endian, Ds64, FormHeader, LIST, JUNK, SIZE_PLACEHOLDER};
+use super::{round2up, resolve_size, ds64_size, chunk_too_big, list_too_big};
+
+/// Size of block used when moving data.
+const MOVE_BLOCK: usize = 64 * 1024;
+
+const IDX1: FourCC = FourCC([b'i', b'd', b'x', b'1']);
+const INDX: FourCC = FourCC([b'i', b'n', b'd', b'x']);
+
+/// Chunks holding file offsets of other chunks: AVI `idx1`, OpenDML `indx` and `ix##`.
+fn has_offsets(fcc: FourCC) -> bool {
+    fcc == IDX1 || fcc == INDX || (&fcc.0[..2] == b"ix" && fcc.0[2..].iter().all(|c| c.is_ascii_digit()))
+}
+
+/// Size field of a list enclosing edited chunk.
+#[derive(Clone, Copy, Debug)]
+struct Ancestor {
+    /// Offset of 32-bit size field.
+    size_offset: u64,
+    /// Offset of `riff_size` in `ds64` when size field holds placeholder.
+    ds64_offset: Option<u64>,
+}
+
+/// Chunk found by `RiffEditor::locate`.
+#[derive(Clone, Debug)]
+struct Location {
+    endian: Endian,
+    ancestors: Vec<Ancestor>,
+    size: u64,
+    /// End of enclosing list payload.
+    parent_end: u64,
+}
+
+/// Edits RIFF file in place.
+///
+/// Chunks are addressed by absolute header offset, as returned by `Chunk::header_offset`.
+/// Resized chunks take or give space from following `JUNK` chunk. Only when there is
+/// not enough of it the rest of the file is moved and enclosing `LIST`/`RIFF` sizes are updated.
+///
+/// Moving is refused if the file has `idx1`, `indx` or `ix##` chunks anywhere, since
+/// offsets stored in them would point to wrong data afterwards. Leave `JUNK` after
+/// chunks which are going to be edited in such files.
+pub struct RiffEditor<T>
+    where T: Read + Write + Seek
+{
+    stream: T,
+}
+
+impl<T> RiffEditor<T>
+    where T: Read + Write + Seek
+{
+    pub fn new(stream: T) -> Self {
+        RiffEditor { stream: stream }
+    }
+
+    pub fn release(self) -> T {
+        self.stream
+    }
+
+    /// Overwrite part of chunk payload starting at `pos`. Chunk size is not changed.
+    pub fn overwrite(&mut self, offset: u64, pos: u64, data: &[u8]) -> io::Result<()> {
+        let location = self.locate(offset)?;
+        if pos + data.len() as u64 > location.size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Write of {} bytes at {} doesn't fit chunk of {} bytes", data.len(), pos, location.size)));
+        }
+        self.stream.seek(SeekFrom::Start(offset + 8 + pos))?;
+        self.stream.write_all(data)
+    }
+
+    /// Replace whole payload of chunk at `offset`.
+    ///
+    /// Returns how far data after the chunk was moved, which is zero unless
+    /// following `JUNK` chunk was missing or too small. Negative when freed space
+    /// can't hold `JUNK` header and data is moved back instead. File length never
+    /// shrinks, bytes left behind at its end are zeroed.
+    pub fn replace(&mut self, offset: u64, data: &[u8]) -> io::Result<i64> {
+        let location = self.locate(offset)?;
+        let new_size = data.len() as u64;
+        if new_size >= SIZE_PLACEHOLDER as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Chunk is too big: {}", new_size)));
+        }
+        let old_end = offset + 8 + round2up(location.size);
+        let new_end = offset + 8 + round2up(new_size);
+
+        // Space after the chunk which can be reused: following JUNK chunk.
+        let mut slack_end = old_end;
+        if old_end + 8 <= location.parent_end {
+            let (fcc, size) = self.read_header(old_end, location.endian)?;
+            if fcc == JUNK && size != SIZE_PLACEHOLDER as u64 {
+                slack_end = cmp::min(old_end + 8 + round2up(size), location.parent_end);
+            }
+        }
+
+        let left = slack_end as i64 - new_end as i64;
+        // Not enough room for JUNK header if 0 < left < 8, close the gap.
+        let shift = if left < 8 { -left } else { 0 };
+
+        if shift != 0 {
+            if let Some((fcc, at)) = self.find_offsets()? {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Can't move data after {}: {} at {} holds file offsets", slack_end, fcc, at)));
+            }
+            self.move_tail(slack_end, shift)?;
+            for ancestor in &location.ancestors {
+                self.resize(ancestor, shift, location.endian)?;
+            }
+        }
+
+        self.write_size(offset + 4, new_size, location.endian)?;
+        self.stream.seek(SeekFrom::Start(offset + 8))?;
+        self.stream.write_all(data)?;
+        if new_size % 2 == 1 {
+            self.stream.write_all(&[0])?;
+        }
+        let junk = (slack_end as i64 + shift) as u64 - new_end;
+        if junk >= 8 {
+            self.stream.write_all(&JUNK.0)?;
+            self.write_size(new_end + 4, junk - 8, location.endian)?;
+        }
+        Ok(shift)
+    }
+
+    /// Find chunk at `offset` and all lists enclosing it.
+    fn locate(&mut self, offset: u64) -> io::Result<Location> {
+        let end = self.stream.seek(SeekFrom::End(0))?;
+        let mut pos = 0;
+        while let Some(header) = self.read_form_header(pos, end)? {
+            let mut form = Ancestor {
+                size_offset: pos + 4,
+                ds64_offset: None,
+            };
+            let size = if header.is_rf64() && header.size == SIZE_PLACEHOLDER {
+                let mut ds64_header = [0; 8];
+                self.stream.seek(SeekFrom::Start(pos + 12))?;
+                self.stream.read_exact(&mut ds64_header)?;
+                ds64_size(&ds64_header)?;
+                form.ds64_offset = Some(pos + 20);
+                self.read_u64(pos + 20)?
+            } else {
+                header.size as u64
+            };
+            let form_end = pos + 8 + size;
+            if offset > pos && offset < form_end {
+                return self.locate_in(offset, header.endian, vec![form], pos + 12, cmp::min(form_end, end));
+            }
+            pos += 8 + round2up(size);
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("No chunk at {}", offset)))
+    }
+
+    /// Form header at `pos`, `None` at the end of file or non-RIFF data.
+    fn read_form_header(&mut self, pos: u64, end: u64) -> io::Result<Option<FormHeader>> {
+        if pos + 12 > end {
+            return Ok(None);
+        }
+        let mut header = [0; 12];
+        self.stream.seek(SeekFrom::Start(pos))?;
+        self.stream.read_exact(&mut header)?;
+        Ok(FormHeader::parse(&header))
+    }
+
+    /// First chunk holding file offsets, with its header offset.
+    fn find_offsets(&mut self) -> io::Result<Option<(FourCC, u64)>> {
+        let end = self.stream.seek(SeekFrom::End(0))?;
+        let mut pos = 0;
+        while let Some(header) = self.read_form_header(pos, end)? {
+            let ds64 = if header.is_rf64() {
+                Some(self.read_ds64(pos + 12)?)
+            } else {
+                None
+            };
+            let size = header.payload_size(ds64.as_ref())?;
+            let form_end = cmp::min(pos + 12 + size, end);
+            if let Some(found) = self.find_offsets_in(pos + 12, form_end, header.endian, ds64.as_ref())? {
+                return Ok(Some(found));
+            }
+            pos += 12 + round2up(size);
+        }
+        Ok(None)
+    }
+
+    fn find_offsets_in(&mut self, mut pos: u64, end: u64, endian: Endian, ds64: Option<&Ds64>) -> io::Result<Option<(FourCC, u64)>> {
+        while pos + 8 <= end {
+            let fcc = self.read_fourcc(pos)?;
+            let size = resolve_size(fcc, endian.read_u32(&mut self.stream)?, ds64)?;
+            if has_offsets(fcc) {
+                return Ok(Some((fcc, pos)));
+            }
+            let node_end = cmp::min(pos + 8 + size, end);
+            if fcc == LIST {
+                if let Some(found) = self.find_offsets_in(pos + 12, node_end, endian, ds64)? {
+                    return Ok(Some(found));
+                }
+            }
+            pos += 8 + round2up(size);
+        }
+        Ok(None)
+    }
+
+    fn locate_in(&mut self, offset: u64, endian: Endian, mut ancestors: Vec<Ancestor>, mut pos: u64, mut end: u64) -> io::Result<Location> {
+        while pos + 8 <= end {
+            let (fcc, size) = self.read_header(pos, endian)?;
+            if size == SIZE_PLACEHOLDER as u64 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Can't edit inside {} with size from ds64", fcc)));
+            }
+            if pos + 8 + size > end {
+                let left = end - pos - 8;
+                return Err(if fcc == LIST { list_too_big(size, left) } else { chunk_too_big(size, left) });
+            }
+            if pos == offset {
+                if fcc == LIST {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Node at {} is a list", offset)));
+                }
+                return Ok(Location {
+                    endian: endian,
+                    ancestors: ancestors,
+                    size: size,
+                    parent_end: end,
+                });
+            }
+            if fcc == LIST && offset > pos && offset < pos + 8 + size {
+                ancestors.push(Ancestor {
+                    size_offset: pos + 4,
+                    ds64_offset: None,
+                });
+                end = pos + 8 + size;
+                pos += 12;
+                continue;
+            }
+            pos += 8 + round2up(size);
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("No chunk at {}", offset)))
+    }
+
+    /// Move everything from `from` to the end of stream by `shift` bytes.
+    /// When moving back, bytes freed at the end are zeroed.
+    fn move_tail(&mut self, from: u64, shift: i64) -> io::Result<()> {
+        let end = self.stream.seek(SeekFrom::End(0))?;
+        let mut buf = vec![0; MOVE_BLOCK];
+        if shift > 0 {
+            let mut block_end = end;
+            while block_end > from {
+                let block_start = cmp::max(from, block_end.saturating_sub(MOVE_BLOCK as u64));
+                let len = (block_end - block_start) as usize;
+                self.stream.seek(SeekFrom::Start(block_start))?;
+                self.stream.read_exact(&mut buf[..len])?;
+                self.stream.seek(SeekFrom::Start(block_start + shift as u64))?;
+                self.stream.write_all(&buf[..len])?;
+                block_end = block_start;
+            }
+        } else {
+            let back = (-shift) as u64;
+            let mut block_start = from;
+            while block_start < end {
+                let len = cmp::min(end - block_start, MOVE_BLOCK as u64) as usize;
+                self.stream.seek(SeekFrom::Start(block_start))?;
+                self.stream.read_exact(&mut buf[..len])?;
+                self.stream.seek(SeekFrom::Start(block_start - back))?;
+                self.stream.write_all(&buf[..len])?;
+                block_start += len as u64;
+            }
+            self.stream.seek(SeekFrom::Start(end - back))?;
+            self.stream.write_all(&vec![0; back as usize])?;
+        }
+        Ok(())
+    }
+
+    fn resize(&mut self, ancestor: &Ancestor, shift: i64, endian: Endian) -> io::Result<()> {
+        match ancestor.ds64_offset {
+            Some(ds64_offset) => {
+                let size = self.read_u64(ds64_offset)?;
+                self.stream.seek(SeekFrom::Start(ds64_offset))?;
+                self.stream.write_u64::<LittleEndian>((size as i64 + shift) as u64)
+            }
+            None => {
+                let (_, size) = self.read_header(ancestor.size_offset - 4, endian)?;
+                let size = (size as i64 + shift) as u64;
+                if size >= SIZE_PLACEHOLDER as u64 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("List at {} grows beyond 32-bit size", ancestor.size_offset - 4)));
+                }
+                self.write_size(ancestor.size_offset, size, endian)
+            }
+        }
+    }
+
+    fn write_size(&mut self, at: u64, size: u64, endian: Endian) -> io::Result<()> {
+        self.stream.seek(SeekFrom::Start(at))?;
+        match endian {
+            Endian::Little => self.stream.write_u32::<LittleEndian>(size as u32),
+            Endian::Big => self.stream.write_u32::<BigEndian>(size as u32),
+        }
+    }
+
+    fn read_fourcc(&mut self, at: u64) -> io::Result<FourCC> {
+        let mut fcc = [0; 4];
+        self.stream.seek(SeekFrom::Start(at))?;
+        self.stream.read_exact(&mut fcc)?;
+        Ok(FourCC(fcc))
+    }
+
+    fn read_header(&mut self, at: u64, endian: Endian) -> io::Result<(FourCC, u64)> {
+        let fcc = self.read_fourcc(at)?;
+        let size = endian.read_u32(&mut self.stream)?;
+        Ok((fcc, size as u64))
+    }
+
+    /// Read `ds64` chunk at `at`. Stream isn't `Debug`, so payload is read into memory first.
+    fn read_ds64(&mut self, at: u64) -> io::Result<Ds64> {
+        let mut header = [0; 8];
+        self.stream.seek(SeekFrom::Start(at))?;
+        self.stream.read_exact(&mut header)?;
+        let size = ds64_size(&header)?;
+        let mut data = vec![];
+        (&mut self.stream).take(size).read_to_end(&mut data)?;
+        Ds64::deser(&mut &data[..])
+    }
+
+    fn read_u64(&mut self, at: u64) -> io::Result<u64> {
+        self.stream.seek(SeekFrom::Start(at))?;
+        Endian::Little.read_u64(&mut self.stream)
+    }
+}
+
+impl<T> Debug for RiffEditor<T>
+    where T: Read + Write + Seek
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "RiffEditor")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use fourcc::FourCC;
+    use riff::{Riff, RiffWriter, List, Node, JUNK};
+    use super::RiffEditor;
+
+    fn fcc(name: &str) -> FourCC {
+        FourCC::from_str(name).unwrap()
+    }
+
+    /// `RIFF TEST { LIST INFO { abcd <data> [JUNK <junk>] } <tail> "uv" }`
+    fn file(data: &[u8], junk: Option<usize>, tail: &str) -> Vec<u8> {
+        let mut writer = RiffWriter::new(Cursor::new(vec![]));
+        writer.begin_riff(fcc("TEST")).unwrap();
+        writer.begin_list(fcc("INFO")).unwrap();
+        writer.write_chunk(fcc("abcd"), data).unwrap();
+        if let Some(junk) = junk {
+            writer.write_chunk(JUNK, &vec![0; junk]).unwrap();
+        }
+        writer.end().unwrap();
+        writer.write_chunk(fcc(tail), b"uv").unwrap();
+        writer.end().unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn describe(list: &mut List<Cursor<Vec<u8>>>, out: &mut Vec<String>) {
+        out.push(format!("{} {}", list.fourcc(), list.size()));
+        for node in list.iter() {
+            match node.unwrap() {
+                Node::List(mut list) => describe(&mut list, out),
+                Node::Chunk(mut chunk) => {
+                    let mut data = vec![];
+                    chunk.read().read_to_end(&mut data).unwrap();
+                    if chunk.fourcc() == JUNK {
+                        data.clear();
+                    }
+                    out.push(format!("{} {} {}", chunk.fourcc(), chunk.size(), String::from_utf8_lossy(&data)));
+                }
+            }
+        }
+    }
+
+    /// Replace `abcd` payload, returning shift and tree of edited file.
+    fn edit(data: Vec<u8>, new: &[u8]) -> (i64, usize, Vec<String>) {
+        let offset = {
+            let mut riff = Riff::new(Cursor::new(data.clone())).unwrap();
+            let node = riff.find("TEST/INFO/abcd").unwrap().unwrap();
+            node.header_offset()
+        };
+        let mut editor = RiffEditor::new(Cursor::new(data));
+        let shift = editor.replace(offset, new).unwrap();
+        let data = editor.release().into_inner();
+        let len = data.len();
+        let mut riff = Riff::new(Cursor::new(data)).unwrap();
+        let mut out = vec![];
+        for form in riff.iter() {
+            describe(&mut form.unwrap(), &mut out);
+        }
+        (shift, len, out)
+    }
+
+    #[test]
+    fn grow_moves_tail() {
+        let data = file(b"xy", None, "efgh");
+        let len = data.len();
+        let (shift, new_len, tree) = edit(data, b"12345");
+        assert_eq!((shift, new_len), (4, len + 4));
+        assert_eq!(tree, vec!["TEST 36", "INFO 14", "abcd 5 12345", "efgh 2 uv"]);
+    }
+
+    #[test]
+    fn grow_into_junk() {
+        let data = file(b"xy", Some(8), "efgh");
+        let len = data.len();
+        let (shift, new_len, tree) = edit(data, b"123456");
+        assert_eq!((shift, new_len), (0, len));
+        assert_eq!(tree, vec!["TEST 48", "INFO 26", "abcd 6 123456", "JUNK 4 ", "efgh 2 uv"]);
+    }
+
+    #[test]
+    fn shrink_leaves_junk() {
+        let data = file(b"0123456789abcdef", None, "efgh");
+        let len = data.len();
+        let (shift, new_len, tree) = edit(data, b"xyz");
+        assert_eq!((shift, new_len), (0, len));
+        assert_eq!(tree, vec!["TEST 46", "INFO 24", "abcd 3 xyz", "JUNK 4 ", "efgh 2 uv"]);
+    }
+
+    #[test]
+    fn small_shrink_keeps_length() {
+        let data = file(b"1234", None, "efgh");
+        let len = data.len();
+        let (shift, new_len, tree) = edit(data, b"xy");
+        assert_eq!((shift, new_len), (-2, len));
+        assert_eq!(tree, vec!["TEST 32", "INFO 10", "abcd 2 xy", "efgh 2 uv"]);
+    }
+
+    #[test]
+    fn refuses_to_move_indexed_data() {
+        let data = file(b"xy", None, "idx1");
+        let offset = 24;
+        let mut editor = RiffEditor::new(Cursor::new(data.clone()));
+        let err = editor.replace(offset, b"12345").unwrap_err();
+        assert!(err.to_string().contains("idx1"), "{}", err);
+        assert_eq!(editor.release().into_inner(), data);
+        // Same size edit doesn't move anything.
+        let mut editor = RiffEditor::new(Cursor::new(data));
+        assert_eq!(editor.replace(offset, b"12").unwrap(), 0);
+    }
+}
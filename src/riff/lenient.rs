@@ -0,0 +1,379 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::cmp;
+use std::mem;
+
+use fourcc::FourCC;
+use deser::Deser;
+
+use super::{Node, List, Chunk, RiffIter, IOBuffer, Endian, LIST};
+use super::round2up;
+
+/// Size of window used when scanning for next header.
+const SCAN_WINDOW: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// Node is bigger than what's left of enclosing list or file.
+    TooBig,
+    /// Header doesn't look like valid FourCC and size.
+    BadHeader,
+    /// Data is too short to hold a header.
+    Truncated,
+}
+
+/// Damage found by lenient iteration.
+#[derive(Clone, Copy, Debug)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// Absolute offset of damaged header.
+    pub offset: u64,
+    pub fcc: FourCC,
+    /// Size declared in header.
+    pub expected: u64,
+    /// Size actually available.
+    pub actual: u64,
+    /// Absolute offset where parsing resumed, if any.
+    pub resumed: Option<u64>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?} at {:#x}: {} declares {} bytes, {} available", self.kind, self.offset, self.fcc, self.expected, self.actual)?;
+        match self.resumed {
+            Some(offset) => write!(f, ", resumed at {:#x}", offset),
+            None => write!(f, ", skipped to end"),
+        }
+    }
+}
+
+/// Item of lenient iteration.
+#[derive(Debug)]
+pub enum Lenient<'a, T>
+    where T: 'a + Read + Seek + Debug
+{
+    Node(Node<'a, T>),
+    Damaged(Diagnostic),
+}
+
+impl<'a, T> Lenient<'a, T>
+    where T: 'a + Read + Seek + Debug
+{
+    pub fn node(self) -> Option<Node<'a, T>> {
+        match self {
+            Lenient::Node(node) => Some(node),
+            Lenient::Damaged(_) => None,
+        }
+    }
+}
+
+/// Printable ASCII, not starting with space.
+pub(crate) fn is_plausible(fcc: FourCC) -> bool {
+    fcc.0[0] != b' ' && fcc.0.iter().all(|&c| c >= 0x20 && c <= 0x7E)
+}
+
+/// Find first plausible `FourCC` + size header at or after current position.
+/// Position is left at the header or at the end of the buffer.
+fn resync<'a, T>(iobuff: &mut IOBuffer<'a, T>, endian: Endian) -> io::Result<Option<u64>>
+    where T: 'a + Read + Seek + Debug
+{
+    let mut window = vec![0; SCAN_WINDOW];
+    loop {
+        let base = iobuff.pos;
+        let len = cmp::min(iobuff.amount_left(), SCAN_WINDOW as u64) as usize;
+        if len < 8 {
+            iobuff.seek(SeekFrom::End(0))?;
+            return Ok(None);
+        }
+        iobuff.read_exact(&mut window[..len])?;
+        for i in 0..len - 7 {
+            let fcc = FourCC::from_slice(&window[i..i + 4]).unwrap();
+            if !is_plausible(fcc) {
+                continue;
+            }
+            let size = endian.read_u32(&mut &window[i + 4..i + 8])? as u64;
+            let left = iobuff.size - (base + i as u64 + 8);
+            if size <= left && (fcc != LIST || size >= mem::size_of::<FourCC>() as u64) {
+                iobuff.seek(SeekFrom::Start(base + i as u64))?;
+                return Ok(Some(iobuff.start + base + i as u64));
+            }
+        }
+        // Keep last bytes which may start a header split by window boundary.
+        iobuff.seek(SeekFrom::Start(base + len as u64 - 7))?;
+    }
+}
+
+//
+
+/// Lenient iterator over sub-nodes of `List`.
+pub struct LenientListIter<'a, 'b, T>
+    where T: 'a + Read + Seek + Debug,
+          'a: 'b
+{
+    inner: &'b mut List<'a, T>,
+    pending: Option<Node<'a, T>>,
+    /// Failed rewind to list start, reported as first item.
+    error: Option<io::Error>,
+}
+
+impl<'a, 'b, T> LenientListIter<'a, 'b, T>
+    where T: 'a + Read + Seek + Debug,
+          'a: 'b
+{
+    pub(super) fn new(list: &'b mut List<'a, T>) -> Self {
+        let error = list.iobuff.seek(SeekFrom::Start(0)).err();
+        LenientListIter {
+            inner: list,
+            pending: None,
+            error: error,
+        }
+    }
+
+    fn read_next(&mut self) -> io::Result<Option<Lenient<'a, T>>> {
+        if let Some(node) = self.pending.take() {
+            return Ok(Some(Lenient::Node(node)));
+        }
+        let list = &mut *self.inner;
+        let left = list.iobuff.amount_left();
+        if left == 0 {
+            return Ok(None);
+        }
+        let offset = list.iobuff.pos;
+        if left < mem::size_of::<(FourCC, u32)>() as u64 {
+            list.iobuff.seek(SeekFrom::End(0))?;
+            return Ok(Some(Lenient::Damaged(Diagnostic {
+                kind: DiagnosticKind::Truncated,
+                offset: list.iobuff.start + offset,
+                fcc: FourCC([0; 4]),
+                expected: mem::size_of::<(FourCC, u32)>() as u64,
+                actual: left,
+                resumed: None,
+            })));
+        }
+
+        let fcc = FourCC::deser(&mut list.iobuff)?;
+        let size = list.read_size(fcc)?;
+        let avail = list.iobuff.amount_left();
+        let mut diagnostic = Diagnostic {
+            kind: DiagnosticKind::BadHeader,
+            offset: list.iobuff.start + offset,
+            fcc: fcc,
+            expected: size,
+            actual: avail,
+            resumed: None,
+        };
+
+        if !is_plausible(fcc) || (fcc == LIST && size < mem::size_of::<FourCC>() as u64) {
+            list.iobuff.seek(SeekFrom::Start(offset + 1))?;
+            diagnostic.resumed = resync(&mut list.iobuff, list.endian)?;
+            return Ok(Some(Lenient::Damaged(diagnostic)));
+        }
+
+        if fcc == LIST {
+            if avail < mem::size_of::<FourCC>() as u64 {
+                list.iobuff.seek(SeekFrom::End(0))?;
+                diagnostic.kind = DiagnosticKind::Truncated;
+                return Ok(Some(Lenient::Damaged(diagnostic)));
+            }
+            let size = size - mem::size_of::<FourCC>() as u64;
+            let sub_fcc = FourCC::deser(&mut list.iobuff)?;
+            let avail = list.iobuff.amount_left();
+            let clamped = cmp::min(size, avail);
+            let slice = list.take_stream_slice(clamped)?;
+            list.iobuff.seek(SeekFrom::Current(cmp::min(round2up(clamped), avail) as i64))?;
+            let sub = Node::List(List {
+                fcc: sub_fcc,
+                iobuff: slice,
+                ds64: list.ds64.clone(),
+                endian: list.endian,
+            });
+            if clamped < size {
+                // Truncated list still holds complete nodes, yield it after diagnostic.
+                diagnostic.kind = DiagnosticKind::TooBig;
+                diagnostic.expected = size;
+                diagnostic.actual = avail;
+                diagnostic.resumed = Some(list.iobuff.start + offset);
+                self.pending = Some(sub);
+                return Ok(Some(Lenient::Damaged(diagnostic)));
+            }
+            Ok(Some(Lenient::Node(sub)))
+        } else {
+            if size > avail {
+                diagnostic.kind = DiagnosticKind::TooBig;
+                diagnostic.resumed = resync(&mut list.iobuff, list.endian)?;
+                return Ok(Some(Lenient::Damaged(diagnostic)));
+            }
+            let slice = list.take_stream_slice(size)?;
+            list.iobuff.seek(SeekFrom::Current(cmp::min(round2up(size), avail) as i64))?;
+            Ok(Some(Lenient::Node(Node::Chunk(Chunk {
+                fcc: fcc,
+                iobuff: slice,
+                endian: list.endian,
+            }))))
+        }
+    }
+}
+
+impl<'a, 'b, T> Iterator for LenientListIter<'a, 'b, T>
+    where T: 'a + Read + Seek + Debug,
+          'a: 'b
+{
+    type Item = io::Result<Lenient<'a, T>>;
+
+    fn next(&mut self) -> Option<io::Result<Lenient<'a, T>>> {
+        let result = match self.error.take() {
+            Some(err) => Err(err),
+            None => self.read_next(),
+        };
+        match result {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => None,
+            Err(err) => {
+                // Stop on real I/O errors.
+                let _ = self.inner.iobuff.seek(SeekFrom::End(0));
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+//
+
+/// Lenient iterator over top level forms. Forms reaching past the end of file are truncated.
+pub struct LenientRiffIter<'a, T>
+    where T: 'a + Read + Seek + Debug
+{
+    inner: RiffIter<'a, T>,
+    pending: Option<List<'a, T>>,
+}
+
+impl<'a, T> LenientRiffIter<'a, T>
+    where T: 'a + Read + Seek + Debug
+{
+    pub(super) fn new(iter: RiffIter<'a, T>) -> Self {
+        LenientRiffIter {
+            inner: iter,
+            pending: None,
+        }
+    }
+
+    fn read_next(&mut self) -> io::Result<Option<Lenient<'a, T>>> {
+        if let Some(list) = self.pending.take() {
+            return Ok(Some(Lenient::Node(Node::List(list))));
+        }
+        let iter = &mut self.inner;
+        if iter.iobuff.amount_left() < mem::size_of::<(FourCC, u32, FourCC)>() as u64 {
+            return Ok(None);
+        }
+        let offset = iter.iobuff.pos;
+        let (header, size, ds64) = match iter.read_form_header()? {
+            Some(form) => form,
+            None => return Ok(None),
+        };
+        let avail = iter.iobuff.amount_left();
+        let clamped = cmp::min(size, avail);
+        let slice = iter.take_stream_slice(clamped)?;
+        iter.iobuff.seek(SeekFrom::Current(cmp::min(round2up(clamped), avail) as i64))?;
+        let list = List {
+            fcc: header.fcc,
+            iobuff: slice,
+            ds64: ds64,
+            endian: header.endian,
+        };
+        if clamped < size {
+            self.pending = Some(list);
+            return Ok(Some(Lenient::Damaged(Diagnostic {
+                kind: DiagnosticKind::TooBig,
+                offset: offset,
+                fcc: header.id,
+                expected: size,
+                actual: avail,
+                resumed: Some(offset),
+            })));
+        }
+        Ok(Some(Lenient::Node(Node::List(list))))
+    }
+}
+
+impl<'a, T> Iterator for LenientRiffIter<'a, T>
+    where T: 'a + Read + Seek + Debug
+{
+    type Item = io::Result<Lenient<'a, T>>;
+
+    fn next(&mut self) -> Option<io::Result<Lenient<'a, T>>> {
+        match self.read_next() {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => None,
+            Err(err) => {
+                let _ = self.inner.iobuff.seek(SeekFrom::End(0));
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use fourcc::FourCC;
+    use riff::{Riff, Node};
+    use super::{Lenient, DiagnosticKind, is_plausible};
+
+    #[test]
+    fn plausible_fourcc() {
+        assert!(is_plausible(FourCC::from_str("IDIT").unwrap()));
+        assert!(is_plausible(FourCC::from_str("cue ").unwrap()));
+        assert!(is_plausible(FourCC::from_str("d-.~").unwrap()));
+        assert!(!is_plausible(FourCC::from_str(" abc").unwrap()));
+        assert!(!is_plausible(FourCC([b'a', 0, b'b', b'c'])));
+        assert!(!is_plausible(FourCC([b'a', 0x80, b'b', b'c'])));
+    }
+
+    fn describe(item: Lenient<Cursor<Vec<u8>>>) -> String {
+        match item {
+            Lenient::Node(Node::Chunk(chunk)) => format!("chunk {} {}", chunk.fourcc(), chunk.size()),
+            Lenient::Node(Node::List(list)) => format!("list {} {}", list.fourcc(), list.size()),
+            Lenient::Damaged(diag) => format!("{:?} {:#x} {} {:?}", diag.kind, diag.offset, diag.fcc, diag.resumed),
+        }
+    }
+
+    #[test]
+    fn resync_after_garbage() {
+        // Garbage header, then valid chunk, then chunk running past the end of form.
+        let data = b"RIFF\x22\0\0\0TEST\
+                     \x01\x02\x03\x04\x05\x06\
+                     abcd\x02\0\0\0xy\
+                     efgh\x20\0\0\0123456".to_vec();
+        let mut riff = Riff::new(Cursor::new(data)).unwrap();
+        let mut iter = riff.iter_lenient();
+        let mut form = iter.next().unwrap().unwrap().node().unwrap().list_or(()).unwrap();
+        let items = form.iter_lenient().map(|item| describe(item.unwrap())).collect::<Vec<_>>();
+        assert_eq!(items, vec![
+            format!("BadHeader 0xc {} Some(18)", FourCC([1, 2, 3, 4])),
+            "chunk abcd 2".to_string(),
+            "TooBig 0x1c efgh None".to_string(),
+        ]);
+        assert_eq!(form.iter_lenient().filter_map(|item| item.unwrap().node()).count(), 1);
+    }
+
+    #[test]
+    fn truncated_form() {
+        let data = b"RIFF\x40\0\0\0TESTabcd\x02\0\0\0xy".to_vec();
+        let mut riff = Riff::new(Cursor::new(data)).unwrap();
+        let items = riff.iter_lenient().map(|item| item.unwrap()).collect::<Vec<_>>();
+        assert_eq!(items.len(), 2);
+        match items[0] {
+            Lenient::Damaged(diag) => {
+                assert_eq!(diag.kind, DiagnosticKind::TooBig);
+                assert_eq!((diag.expected, diag.actual), (0x3c, 10));
+            }
+            _ => panic!("expected diagnostic"),
+        }
+        let mut form = match items.into_iter().nth(1) {
+            Some(Lenient::Node(node)) => node.list_or(()).unwrap(),
+            _ => panic!("expected form"),
+        };
+        assert_eq!(form.iter().count(), 1);
+    }
+}
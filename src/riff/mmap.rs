@@ -0,0 +1,121 @@
+use std::fs::File;
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use memmap::Mmap;
+
+use super::{Riff, RiffSlice, ListSlice, ChunkSlice, List, Chunk, IOBuffer};
+
+/// Read-only memory mapping of a RIFF file.
+///
+/// The file must not be modified by anyone while it is mapped.
+#[derive(Debug)]
+pub struct MappedFile {
+    map: Mmap,
+}
+
+impl MappedFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Self::from_file(&file)
+    }
+
+    pub fn from_file(file: &File) -> io::Result<Self> {
+        let map = unsafe { Mmap::map(file)? };
+        Ok(MappedFile { map: map })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Zero-copy tree over the mapping.
+    pub fn slice(&self) -> RiffSlice {
+        RiffSlice::new(&self.map)
+    }
+
+    /// Seekable tree over the mapping. Can be passed to `Demuxer::from_riff`.
+    ///
+    /// Reads are copied straight from the mapping, without read-ahead buffer.
+    /// `List::slice` and `Chunk::slice` switch to zero-copy slices of the mapping.
+    pub fn riff(&self) -> io::Result<Riff<Cursor<&[u8]>>> {
+        Riff::with_read_ahead(Cursor::new(&self.map[..]), 0)
+    }
+}
+
+impl<'a, 'm> IOBuffer<'a, Cursor<&'m [u8]>> {
+    fn bytes(&self) -> &'m [u8] {
+        let data: &'m [u8] = *self.inner.borrow().inner.get_ref();
+        &data[self.start as usize..(self.start + self.size) as usize]
+    }
+}
+
+impl<'a, 'm> List<'a, Cursor<&'m [u8]>> {
+    /// Payload of the list after list type, borrowed from underlying memory.
+    pub fn bytes(&self) -> &'m [u8] {
+        self.iobuff.bytes()
+    }
+
+    /// Same list as zero-copy slice of underlying memory.
+    pub fn slice(&self) -> ListSlice<'m> {
+        ListSlice::from_parts(self.fcc, self.iobuff.bytes(), self.ds64.clone(), self.endian)
+    }
+}
+
+impl<'a, 'm> Chunk<'a, Cursor<&'m [u8]>> {
+    /// Payload of the chunk, borrowed from underlying memory.
+    pub fn bytes(&self) -> &'m [u8] {
+        self.iobuff.bytes()
+    }
+
+    /// Same chunk as zero-copy slice of underlying memory.
+    pub fn slice(&self) -> ChunkSlice<'m> {
+        ChunkSlice::from_parts(self.fcc, self.iobuff.bytes(), self.endian)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::process;
+
+    use fourcc::FourCC;
+    use riff::Node;
+    use super::MappedFile;
+
+    const FILE: &'static [u8] = b"RIFF\x1c\0\0\0TEST\
+                                  abcd\x03\0\0\0xyz\0\
+                                  LIST\x04\0\0\0INFO";
+
+    #[test]
+    fn riff_reads_from_mapping() {
+        let path = env::temp_dir().join(format!("avirs-mmap-{}.riff", process::id()));
+        File::create(&path).unwrap().write_all(FILE).unwrap();
+        let map = MappedFile::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let range = map.data().as_ptr() as usize..map.data().as_ptr() as usize + FILE.len();
+
+        let mut riff = map.riff().unwrap();
+        let mut form = riff.iter().next().unwrap().unwrap();
+        let slice = form.slice();
+        assert_eq!(slice.data().len(), 28 - 4);
+        assert!(range.contains(&(slice.data().as_ptr() as usize)));
+        let mut nodes = form.iter();
+        let mut chunk = match nodes.next().unwrap().unwrap() {
+            Node::Chunk(chunk) => chunk,
+            Node::List(_) => panic!("expected chunk"),
+        };
+        assert_eq!(chunk.slice().data(), b"xyz");
+        assert!(range.contains(&(chunk.bytes().as_ptr() as usize)));
+        let mut payload = String::new();
+        chunk.read().read_to_string(&mut payload).unwrap();
+        assert_eq!(payload, "xyz");
+        match nodes.next().unwrap().unwrap() {
+            Node::List(list) => assert_eq!(list.slice().fourcc(), FourCC([b'I', b'N', b'F', b'O'])),
+            Node::Chunk(_) => panic!("expected list"),
+        }
+        assert_eq!(map.slice().iter().count(), 1);
+    }
+}
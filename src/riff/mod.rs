@@ -10,12 +10,257 @@ use byteorder::{ReadBytesExt, LittleEndian, BigEndian};
 use fourcc::FourCC;
 use deser::Deser;
 
+mod writer;
+mod rf64;
+mod slice;
+mod mmap;
+mod stream;
+mod push;
+mod lenient;
+mod query;
+mod shared;
+mod edit;
+mod doc;
+
+pub use self::writer::RiffWriter;
+pub use self::rf64::{Ds64, RF64, BW64, DS64, SIZE_PLACEHOLDER};
+pub use self::mmap::MappedFile;
+pub use self::stream::{RiffReader, Event, ListHeader, ChunkStream};
+pub use self::push::{PushParser, PushEvent, PushEvents};
+pub use self::lenient::{Lenient, Diagnostic, DiagnosticKind, LenientRiffIter, LenientListIter};
+pub use self::query::Query;
+pub use self::shared::{ReadAt, RiffAt, RiffAtIter, ListAt, ListAtIter, ChunkAt, ChunkAtReader, NodeAt};
+pub use self::edit::RiffEditor;
+pub use self::doc::{RiffDocument, DocNode, DocList, DocChunk};
+pub use self::slice::{RiffSlice, RiffSliceIter, ListSlice, ListSliceIter, ChunkSlice, NodeSlice};
+
 pub const LIST: FourCC = FourCC([b'L', b'I', b'S', b'T']);
 pub const RIFF: FourCC = FourCC([b'R', b'I', b'F', b'F']);
+pub const RIFX: FourCC = FourCC([b'R', b'I', b'F', b'X']);
+pub const JUNK: FourCC = FourCC([b'J', b'U', b'N', b'K']);
+
+/// Byte order of RIFF file. `RIFF` and `RF64` are little endian, `RIFX` is big endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    pub fn read_u16<R: Read>(self, read: &mut R) -> io::Result<u16> {
+        match self {
+            Endian::Little => read.read_u16::<LittleEndian>(),
+            Endian::Big => read.read_u16::<BigEndian>(),
+        }
+    }
+    pub fn read_i16<R: Read>(self, read: &mut R) -> io::Result<i16> {
+        match self {
+            Endian::Little => read.read_i16::<LittleEndian>(),
+            Endian::Big => read.read_i16::<BigEndian>(),
+        }
+    }
+    pub fn read_u32<R: Read>(self, read: &mut R) -> io::Result<u32> {
+        match self {
+            Endian::Little => read.read_u32::<LittleEndian>(),
+            Endian::Big => read.read_u32::<BigEndian>(),
+        }
+    }
+    pub fn read_i32<R: Read>(self, read: &mut R) -> io::Result<i32> {
+        match self {
+            Endian::Little => read.read_i32::<LittleEndian>(),
+            Endian::Big => read.read_i32::<BigEndian>(),
+        }
+    }
+    pub fn read_u64<R: Read>(self, read: &mut R) -> io::Result<u64> {
+        match self {
+            Endian::Little => read.read_u64::<LittleEndian>(),
+            Endian::Big => read.read_u64::<BigEndian>(),
+        }
+    }
+}
+
+fn round2up(value: u64) -> u64 {
+    value + value % 2
+}
+
+/// Size of list payload without list type.
+fn list_payload_size(size: u64) -> io::Result<u64> {
+    size.checked_sub(mem::size_of::<FourCC>() as u64)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("List is too small: {}", size)))
+}
+
+fn chunk_too_big(size: u64, left: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, format!("Chunk is too big. Chunk: {}. Remaining size: {}", size, left))
+}
 
-fn round2up<T: ::std::convert::Into<i64>>(value: T) -> i64 {
-    let value = value.into();
-    value + 1 - (value + 1) % 2
+fn list_too_big(size: u64, left: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, format!("List is too big. List: {}. Remaining size: {}", size, left))
+}
+
+/// Byte order of form starting with `id`. `None` if `id` doesn't start a form.
+fn form_endian(id: FourCC) -> Option<Endian> {
+    match id {
+        RIFF | RF64 | BW64 => Some(Endian::Little),
+        RIFX => Some(Endian::Big),
+        _ => None,
+    }
+}
+
+/// First 12 bytes of a form: id, 32-bit size and form type.
+///
+/// Shared by all backends, so forms are recognised and sized the same way everywhere.
+#[derive(Clone, Copy, Debug)]
+struct FormHeader {
+    id: FourCC,
+    size: u32,
+    fcc: FourCC,
+    endian: Endian,
+}
+
+impl FormHeader {
+    /// Parse form header. `None` if `header` is shorter than 12 bytes or doesn't start with form id.
+    fn parse(header: &[u8]) -> Option<FormHeader> {
+        if header.len() < mem::size_of::<(FourCC, u32, FourCC)>() {
+            return None;
+        }
+        let id = FourCC::from_slice(&header[..4]).unwrap();
+        let endian = form_endian(id)?;
+        Some(FormHeader {
+            id: id,
+            size: endian.read_u32(&mut &header[4..8]).unwrap(),
+            fcc: FourCC::from_slice(&header[8..12]).unwrap(),
+            endian: endian,
+        })
+    }
+
+    /// Form starts with `ds64` chunk holding 64-bit sizes.
+    fn is_rf64(&self) -> bool {
+        self.id == RF64 || self.id == BW64
+    }
+
+    /// Size of payload after form type. Placeholder size is taken from `ds64`.
+    fn payload_size(&self, ds64: Option<&Ds64>) -> io::Result<u64> {
+        let size = match ds64 {
+            Some(ds64) if self.size == SIZE_PLACEHOLDER => ds64.riff_size,
+            _ => self.size as u64,
+        };
+        list_payload_size(size)
+    }
+}
+
+/// Resolve 32-bit size field of node `fcc`, looking up `RF64` placeholder in `ds64` table.
+fn resolve_size(fcc: FourCC, size: u32, ds64: Option<&Ds64>) -> io::Result<u64> {
+    match ds64 {
+        Some(ds64) if size == SIZE_PLACEHOLDER => {
+            ds64.chunk_size(fcc).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("No ds64 size for {}", fcc))
+            })
+        }
+        _ => Ok(size as u64),
+    }
+}
+
+/// Payload size from 8 byte header of `ds64` chunk, which must follow `RF64` form type.
+fn ds64_size(header: &[u8]) -> io::Result<u64> {
+    if header.len() < mem::size_of::<(FourCC, u32)>() || header[..4] != DS64.0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "RF64 form must start with ds64 chunk"));
+    }
+    Ok(Endian::Little.read_u32(&mut &header[4..8])? as u64)
+}
+
+/// Read `ds64` chunk which must follow `RF64` form type.
+fn read_ds64<R: Read + Debug>(read: &mut R) -> io::Result<Ds64> {
+    let mut header = [0; 8];
+    read.read_exact(&mut header)?;
+    let size = ds64_size(&header)?;
+    Ds64::deser(&mut read.take(size))
+}
+
+
+/// Size of read-ahead buffer shared by all slices of a stream.
+const READ_AHEAD: usize = 16 * 1024;
+
+/// Stream shared by all slices of a `Riff`.
+///
+/// Tracks actual stream position to skip redundant seeks and keeps read-ahead buffer,
+/// so small sequential reads don't turn into syscalls.
+#[derive(Clone)]
+struct Source<T>
+    where T: Read + Seek + Debug
+{
+    inner: T,
+    /// Actual position of `inner`.
+    pos: u64,
+    /// Position where last slice stopped. Restored on `Riff::release`.
+    logical: u64,
+    buf: Vec<u8>,
+    buf_start: u64,
+    buf_len: usize,
+    /// Size of read-ahead. Zero for in-memory streams, which are read directly.
+    read_ahead: usize,
+}
+
+impl<T> Source<T>
+    where T: Read + Seek + Debug
+{
+    fn new(inner: T, pos: u64, read_ahead: usize) -> Self {
+        Source {
+            inner: inner,
+            pos: pos,
+            logical: pos,
+            buf: vec![],
+            buf_start: 0,
+            buf_len: 0,
+            read_ahead: read_ahead,
+        }
+    }
+
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.buf_start && offset < self.buf_start + self.buf_len as u64 {
+            let buffered = &self.buf[(offset - self.buf_start) as usize..self.buf_len];
+            let count = cmp::min(buf.len(), buffered.len());
+            buf[..count].copy_from_slice(&buffered[..count]);
+            return Ok(count);
+        }
+        self.seek_to(offset)?;
+        if buf.len() >= self.read_ahead {
+            let read = self.inner.read(buf)?;
+            self.pos += read as u64;
+            return Ok(read);
+        }
+        if self.buf.len() < self.read_ahead {
+            self.buf.resize(self.read_ahead, 0);
+        }
+        self.buf_len = 0;
+        let read = self.inner.read(&mut self.buf[..])?;
+        self.pos += read as u64;
+        self.buf_start = offset;
+        self.buf_len = read;
+        let count = cmp::min(buf.len(), read);
+        buf[..count].copy_from_slice(&self.buf[..count]);
+        Ok(count)
+    }
+
+    fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+        if self.pos != offset {
+            self.pos = self.inner.seek(SeekFrom::Start(offset))?;
+        }
+        Ok(())
+    }
+
+    fn into_inner(mut self) -> T {
+        let logical = self.logical;
+        let _ = self.seek_to(logical);
+        self.inner
+    }
+}
+
+impl<T> Debug for Source<T>
+    where T: Read + Seek + Debug
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Source {{ inner: {:?}, pos: {:?}, buf_start: {:?}, buf_len: {:?} }}", self.inner, self.pos, self.buf_start, self.buf_len)
+    }
 }
 
 
@@ -26,13 +271,13 @@ struct IOBuffer<'a, T>
     start: u64,
     pos: u64,
     size: u64,
-    inner: &'a RefCell<T>,
+    inner: &'a RefCell<Source<T>>,
 }
 
 impl<'a, T> IOBuffer<'a, T>
     where T: 'a + Read + Seek + Debug
 {
-    fn new(iobuff: &'a RefCell<T>, start: u64, size: u64) -> Self {
+    fn new(iobuff: &'a RefCell<Source<T>>, start: u64, size: u64) -> Self {
         IOBuffer {
             start: start,
             pos: 0,
@@ -65,9 +310,7 @@ impl<'a, T> Read for IOBuffer<'a, T>
         if toread == 0 {
             Ok(0)
         } else {
-            let mut inner = self.inner.borrow_mut();
-            inner.seek(SeekFrom::Start(self.start + self.pos))?;
-            let read = inner.read(&mut buf[..toread])?;
+            let read = self.inner.borrow_mut().read_at(&mut buf[..toread], self.start + self.pos)?;
             self.pos += read as u64;
             Ok(read)
         }
@@ -118,7 +361,7 @@ impl<'a, T: 'a + Read + Seek + Debug> Drop for IOBuffer<'a, T>
     where T: 'a + Read + Seek + Debug
 {
     fn drop(&mut self) {
-        self.inner.borrow_mut().seek(SeekFrom::Start(self.start + self.pos));
+        self.inner.borrow_mut().logical = self.start + self.pos;
     }
 }
 
@@ -141,6 +384,18 @@ impl<'a, T> Node<'a, T>
             Node::Chunk(ref item) => item.fourcc(),
         }
     }
+    pub fn header_offset(&self) -> u64 {
+        match *self {
+            Node::List(ref item) => item.header_offset(),
+            Node::Chunk(ref item) => item.header_offset(),
+        }
+    }
+    pub fn data_offset(&self) -> u64 {
+        match *self {
+            Node::List(ref item) => item.data_offset(),
+            Node::Chunk(ref item) => item.data_offset(),
+        }
+    }
     pub fn chunk_or<E>(self, error: E) -> Result<Chunk<'a, T>, E> {
         self.chunk_or_else(|_| error)
     }
@@ -168,17 +423,24 @@ pub struct Riff<T>
     where T: Read + Seek + Debug
 {
     size: u64,
-    stream: RefCell<T>,
+    stream: RefCell<Source<T>>,
+    /// Byte order and `ds64` of the first form, read once by `node_at`.
+    form: RefCell<Option<(Endian, Option<Rc<Ds64>>)>>,
 }
 
 impl<T> Riff<T>
     where T: Read + Seek + Debug
 {
-    pub fn new(mut stream: T) -> io::Result<Self> {
+    pub fn new(stream: T) -> io::Result<Self> {
+        Self::with_read_ahead(stream, READ_AHEAD)
+    }
+
+    fn with_read_ahead(mut stream: T, read_ahead: usize) -> io::Result<Self> {
         let size = stream.seek(io::SeekFrom::End(0))?;
         Ok(Riff {
             size: size,
-            stream: RefCell::new(stream),
+            stream: RefCell::new(Source::new(stream, size, read_ahead)),
+            form: RefCell::new(None),
         })
     }
 
@@ -186,8 +448,75 @@ impl<T> Riff<T>
         RiffIter { iobuff: IOBuffer::new(&self.stream, 0, self.size) }
     }
 
+    /// Iterate over forms, tolerating damaged and truncated data. See `List::iter_lenient`.
+    pub fn iter_lenient<'a>(&'a mut self) -> LenientRiffIter<'a, T> {
+        LenientRiffIter::new(self.iter())
+    }
+
+    /// Rebuild node which header starts at absolute `offset`, as found in OpenDML indices.
+    /// Byte order and `ds64` sizes are taken from the first form.
+    ///
+    /// Offsets in `idx1` are usually relative to the `movi` list type, that is to
+    /// `data_offset() - 4` of `LIST movi`, and have to be made absolute first.
+    pub fn node_at<'a>(&'a self, offset: u64) -> io::Result<Node<'a, T>> {
+        if offset >= self.size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Offset {} is beyond the end of file {}", offset, self.size)));
+        }
+        let (endian, ds64) = self.first_form()?;
+
+        let mut iter = RiffIter { iobuff: IOBuffer::new(&self.stream, 0, self.size) };
+        iter.iobuff.seek(io::SeekFrom::Start(offset))?;
+        let id = match FourCC::deser(&mut iter.iobuff) {
+            Ok(id) if lenient::is_plausible(id) => id,
+            Ok(_) | Err(_) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("No node header at {}", offset)));
+            }
+        };
+        iter.iobuff.seek(io::SeekFrom::Start(offset))?;
+        let node = match form_endian(id) {
+            Some(_) => iter.read_next().map(|list| list.map(Node::List)),
+            None => {
+                let mut list = List {
+                    fcc: id,
+                    iobuff: IOBuffer::new(&self.stream, offset, self.size - offset),
+                    ds64: ds64,
+                    endian: endian,
+                };
+                list.read_next()
+            }
+        };
+        node.unwrap_or_else(|| {
+            Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("No node header at {}", offset)))
+        })
+    }
+
+    /// Byte order and `ds64` of the first form. Files without form header are taken as little endian.
+    fn first_form(&self) -> io::Result<(Endian, Option<Rc<Ds64>>)> {
+        if let Some(ref form) = *self.form.borrow() {
+            return Ok(form.clone());
+        }
+        let form = if self.size < mem::size_of::<(FourCC, u32, FourCC)>() as u64 {
+            (Endian::Little, None)
+        } else {
+            let mut head = RiffIter { iobuff: IOBuffer::new(&self.stream, 0, self.size) };
+            match head.read_form_header()? {
+                Some((header, _, ds64)) => (header.endian, ds64),
+                None => (Endian::Little, None),
+            }
+        };
+        *self.form.borrow_mut() = Some(form.clone());
+        Ok(form)
+    }
+
+    /// Read `buf.len()` bytes at absolute `offset`, without interpreting them.
+    pub(crate) fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut iobuff = IOBuffer::new(&self.stream, 0, self.size);
+        iobuff.seek(io::SeekFrom::Start(offset))?;
+        iobuff.read_exact(buf)
+    }
+
     pub fn release(self) -> T {
-        self.stream.into_inner()
+        self.stream.into_inner().into_inner()
     }
 }
 
@@ -204,30 +533,47 @@ impl<'a, T> RiffIter<'a, T>
         if self.iobuff.amount_left() < mem::size_of::<(FourCC, u32, FourCC)>() as u64 {
             return None;
         }
-        let fcc = FourCC::deser(&mut self.iobuff);
-        match fcc {
-            Ok(fcc) => {
-                match fcc {
-                    RIFF => Some(self.read_next_riff()),
-                    _ => self.iobuff.seek(io::SeekFrom::Current(-4)).err().map(Err),
-                }
-            }
-            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => None,
+        match self.read_form_header() {
+            Ok(Some((header, size, ds64))) => Some(self.read_next_riff(header, size, ds64)),
+            Ok(None) => None,
             Err(err) => Some(Err(err)),
         }
     }
 
-    fn read_next_riff(&mut self) -> io::Result<List<'a, T>> {
-        let size = self.iobuff.read_u32::<LittleEndian>()? - 4;
-        let fcc = FourCC::deser(&mut self.iobuff)?;
-        let slice = self.take_stream_slice(size as u64)?;
+    fn read_next_riff(&mut self, header: FormHeader, size: u64, ds64: Option<Rc<Ds64>>) -> io::Result<List<'a, T>> {
+        let slice = self.take_stream_slice(size)?;
         self.iobuff.seek(io::SeekFrom::Current(round2up(size) as i64))?;
         Ok(List {
-            fcc: fcc,
+            fcc: header.fcc,
             iobuff: slice,
+            ds64: ds64,
+            endian: header.endian,
         })
     }
 
+    /// Read form header with its `ds64` chunk and payload size. Position is left after form type.
+    /// Returns `None` and keeps position if there is no form header at current position.
+    fn read_form_header(&mut self) -> io::Result<Option<(FormHeader, u64, Option<Rc<Ds64>>)>> {
+        let mut bytes = [0; 12];
+        self.iobuff.read_exact(&mut bytes)?;
+        let header = match FormHeader::parse(&bytes) {
+            Some(header) => header,
+            None => {
+                self.iobuff.seek(io::SeekFrom::Current(-(bytes.len() as i64)))?;
+                return Ok(None);
+            }
+        };
+        let ds64 = if header.is_rf64() {
+            // `ds64` is read ahead, it stays the first child of the form.
+            let mut head = self.take_stream_slice(self.iobuff.amount_left())?;
+            Some(Rc::new(read_ds64(&mut head)?))
+        } else {
+            None
+        };
+        let size = header.payload_size(ds64.as_ref().map(|ds64| &**ds64))?;
+        Ok(Some((header, size, ds64)))
+    }
+
     fn take_stream_slice(&self, size: u64) -> io::Result<IOBuffer<'a, T>> {
         self.iobuff.take_slice(size)
     }
@@ -252,6 +598,8 @@ pub struct List<'a, T>
 {
     fcc: FourCC,
     iobuff: IOBuffer<'a, T>,
+    ds64: Option<Rc<Ds64>>,
+    endian: Endian,
 }
 
 
@@ -264,12 +612,35 @@ impl<'a, T> List<'a, T>
         self.iobuff.seek(io::SeekFrom::Start(0)).unwrap();
         ListIter { inner: self }
     }
+    /// Iterate over sub-nodes, reporting damaged headers as diagnostics and resynchronising
+    /// on the next plausible header instead of stopping.
+    pub fn iter_lenient<'b>(&'b mut self) -> LenientListIter<'a, 'b, T>
+        where 'a: 'b
+    {
+        LenientListIter::new(self)
+    }
     pub fn fourcc(&self) -> FourCC {
         self.fcc
     }
     pub fn size(&self) -> u64 {
         self.iobuff.size
     }
+    /// Byte order of enclosing form.
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+    /// Absolute offset of list header: `LIST`/`RIFF` id, size and list type.
+    pub fn header_offset(&self) -> u64 {
+        self.iobuff.start - mem::size_of::<(FourCC, u32, FourCC)>() as u64
+    }
+    /// Absolute offset of list payload after list type.
+    pub fn data_offset(&self) -> u64 {
+        self.iobuff.start
+    }
+    /// `ds64` chunk of enclosing `RF64`/`BW64` form.
+    pub fn ds64(&self) -> Option<&Ds64> {
+        self.ds64.as_ref().map(|ds64| &**ds64)
+    }
     fn read_next(&mut self) -> Option<io::Result<Node<'a, T>>> {
         if self.iobuff.amount_left() < mem::size_of::<FourCC>() as u64 {
             return None;
@@ -292,28 +663,37 @@ impl<'a, T> List<'a, T>
         }
     }
     fn read_list(&mut self) -> io::Result<List<'a, T>> {
-        let size = self.iobuff.read_u32::<LittleEndian>()? - 4;
+        let size = self.read_size(LIST)?;
+        let size = list_payload_size(size)?;
         let fcc = FourCC::deser(&mut self.iobuff)?;
-        let slice = self.take_stream_slice(size as u64)?;
+        let slice = self.take_stream_slice(size)?;
         self.iobuff.seek(io::SeekFrom::Current(round2up(size) as i64))?;
         Ok(List {
             fcc: fcc,
             iobuff: slice,
+            ds64: self.ds64.clone(),
+            endian: self.endian,
         })
     }
 
     fn read_chunk(&mut self, fcc: FourCC) -> io::Result<Chunk<'a, T>> {
-        let mut size = self.iobuff.read_u32::<LittleEndian>()?;
-        if size as u64 > self.iobuff.amount_left() {
-            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("Chunk is too big. Chunk: {}. Remaining size: {}", size, self.iobuff.amount_left())));
+        let size = self.read_size(fcc)?;
+        if size > self.iobuff.amount_left() {
+            return Err(chunk_too_big(size, self.iobuff.amount_left()));
         }
-        let slice = self.take_stream_slice(size as u64)?;
+        let slice = self.take_stream_slice(size)?;
         self.iobuff.seek(io::SeekFrom::Current(round2up(size) as i64))?;
         Ok(Chunk {
             fcc: fcc,
             iobuff: slice,
+            endian: self.endian,
         })
     }
+    /// Read 32-bit size field, resolving `RF64` placeholder through `ds64` table.
+    fn read_size(&mut self, fcc: FourCC) -> io::Result<u64> {
+        let size = self.endian.read_u32(&mut self.iobuff)?;
+        resolve_size(fcc, size, self.ds64())
+    }
     fn take_stream_slice(&self, size: u64) -> io::Result<IOBuffer<'a, T>> {
         self.iobuff.take_slice(size)
     }
@@ -347,6 +727,7 @@ pub struct Chunk<'a, T>
 {
     fcc: FourCC,
     iobuff: IOBuffer<'a, T>,
+    endian: Endian,
 }
 
 impl<'a, T> Chunk<'a, T>
@@ -358,6 +739,18 @@ impl<'a, T> Chunk<'a, T>
     pub fn size(&self) -> u64 {
         self.iobuff.size
     }
+    /// Byte order to decode payload fields with.
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+    /// Absolute offset of chunk header: FourCC and size.
+    pub fn header_offset(&self) -> u64 {
+        self.iobuff.start - mem::size_of::<(FourCC, u32)>() as u64
+    }
+    /// Absolute offset of chunk payload.
+    pub fn data_offset(&self) -> u64 {
+        self.iobuff.start
+    }
     pub fn read<'b>(&'b mut self) -> ChunkReader<'a, 'b, T> {
         self.iobuff.seek(io::SeekFrom::Start(0)).unwrap();
         ChunkReader { inner: self }
@@ -372,6 +765,15 @@ pub struct ChunkReader<'a, 'b, T>
     inner: &'b mut Chunk<'a, T>,
 }
 
+impl<'a, 'b, T> ChunkReader<'a, 'b, T>
+    where T: 'a + Read + Seek + Debug,
+          'a: 'b
+{
+    pub fn endian(&self) -> Endian {
+        self.inner.endian
+    }
+}
+
 impl<'a, 'b, T> Read for ChunkReader<'a, 'b, T>
     where T: 'a + Read + Seek + Debug,
           'a: 'b
@@ -380,3 +782,155 @@ impl<'a, 'b, T> Read for ChunkReader<'a, 'b, T>
         self.inner.iobuff.read(buf)
     }
 }
+
+impl<'a, 'b, T> Seek for ChunkReader<'a, 'b, T>
+    where T: 'a + Read + Seek + Debug,
+          'a: 'b
+{
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.iobuff.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+    use fourcc::FourCC;
+    use riff::RiffWriter;
+    use super::{Riff, Node, Endian};
+
+    /// Stream counting calls which reach the underlying cursor.
+    #[derive(Debug)]
+    struct Counting {
+        inner: Cursor<Vec<u8>>,
+        reads: usize,
+        seeks: usize,
+    }
+
+    impl Read for Counting {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for Counting {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.seeks += 1;
+            self.inner.seek(pos)
+        }
+    }
+
+    const TEST: FourCC = FourCC([b'T', b'E', b'S', b'T']);
+    const ABCD: FourCC = FourCC([b'a', b'b', b'c', b'd']);
+
+    #[test]
+    fn rifx_sizes_are_big_endian() {
+        let data = b"RIFX\0\0\0\x0eTESTabcd\0\0\0\x02xy".to_vec();
+        let mut riff = Riff::new(Cursor::new(data)).unwrap();
+        let mut form = riff.iter().next().unwrap().unwrap();
+        assert_eq!((form.fourcc(), form.endian()), (TEST, Endian::Big));
+        let mut chunk = match form.iter().next().unwrap().unwrap() {
+            Node::Chunk(chunk) => chunk,
+            Node::List(_) => panic!("expected chunk"),
+        };
+        assert_eq!((chunk.fourcc(), chunk.size(), chunk.endian()), (ABCD, 2, Endian::Big));
+        assert_eq!(chunk.endian().read_u16(&mut chunk.read()).unwrap(), 0x7879);
+        let mut payload = vec![];
+        chunk.read().read_to_end(&mut payload).unwrap();
+        assert_eq!(payload, b"xy");
+    }
+
+    #[test]
+    fn small_reads_are_buffered() {
+        let mut writer = RiffWriter::new(Cursor::new(vec![]));
+        writer.begin_riff(TEST).unwrap();
+        let records = (0..1000u32).flat_map(|i| (0..16).map(move |j| (i + j) as u8)).collect::<Vec<u8>>();
+        writer.write_chunk(ABCD, &records).unwrap();
+        writer.end().unwrap();
+        let data = writer.finish().unwrap().into_inner();
+
+        let stream = Counting { inner: Cursor::new(data), reads: 0, seeks: 0 };
+        let mut riff = Riff::new(stream).unwrap();
+        let mut read = vec![];
+        {
+            let mut form = riff.iter().next().unwrap().unwrap();
+            let mut chunk = form.iter().next().unwrap().unwrap().chunk_or(()).unwrap();
+            let mut reader = chunk.read();
+            let mut record = [0; 16];
+            for _ in 0..1000 {
+                reader.read_exact(&mut record).unwrap();
+                read.extend_from_slice(&record);
+            }
+        }
+        assert_eq!(read, records);
+        let stream = riff.release();
+        // 16016 bytes fit in two read-ahead buffers.
+        assert!(stream.reads <= 4, "{} reads", stream.reads);
+        assert!(stream.seeks <= 4, "{} seeks", stream.seeks);
+    }
+
+    /// Form `TEST` holding odd sized chunk and `LIST list` with one chunk.
+    fn nested() -> Vec<u8> {
+        let mut writer = RiffWriter::new(Cursor::new(vec![]));
+        writer.begin_riff(TEST).unwrap();
+        writer.write_chunk(ABCD, b"xyz").unwrap();
+        writer.begin_list(FourCC::from_str("list").unwrap()).unwrap();
+        writer.write_chunk(FourCC::from_str("efgh").unwrap(), b"12").unwrap();
+        writer.end().unwrap();
+        writer.end().unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn payload<T: Read + Seek + ::std::fmt::Debug>(node: Node<T>) -> (FourCC, u64, u64, Vec<u8>) {
+        let mut chunk = node.chunk_or(()).unwrap();
+        let mut payload = vec![];
+        chunk.read().read_to_end(&mut payload).unwrap();
+        (chunk.fourcc(), chunk.size(), chunk.data_offset(), payload)
+    }
+
+    #[test]
+    fn node_at_offsets() {
+        let mut riff = Riff::new(Cursor::new(nested())).unwrap();
+        let (form_offset, chunk_offset, list_offset, inner_offset, expected) = {
+            let mut form = riff.iter().next().unwrap().unwrap();
+            let mut nodes = form.iter().map(|node| node.unwrap()).collect::<Vec<_>>();
+            let mut list = nodes.pop().unwrap().list_or(()).unwrap();
+            let chunk = nodes.pop().unwrap();
+            let inner = list.iter().next().unwrap().unwrap();
+            let offsets = (form.header_offset(), chunk.header_offset(), list.header_offset(), inner.header_offset());
+            (offsets.0, offsets.1, offsets.2, offsets.3, (payload(chunk), list.fourcc(), list.size(), payload(inner)))
+        };
+        assert_eq!((form_offset, chunk_offset, list_offset, inner_offset), (0, 12, 24, 36));
+
+        let form = riff.node_at(form_offset).unwrap().list_or(()).unwrap();
+        assert_eq!((form.fourcc(), form.size(), form.data_offset()), (TEST, 34, 12));
+        assert_eq!(payload(riff.node_at(chunk_offset).unwrap()), expected.0);
+        assert_eq!(expected.0, (ABCD, 3, 20, b"xyz".to_vec()));
+        let mut list = riff.node_at(list_offset).unwrap().list_or(()).unwrap();
+        assert_eq!((list.fourcc(), list.size()), (expected.1, expected.2));
+        assert_eq!(payload(list.iter().next().unwrap().unwrap()), expected.3);
+        assert_eq!(payload(riff.node_at(inner_offset).unwrap()), expected.3);
+    }
+
+    #[test]
+    fn node_at_bad_offsets() {
+        let data = nested();
+        let size = data.len() as u64;
+        let riff = Riff::new(Cursor::new(data)).unwrap();
+        assert_eq!(riff.node_at(size).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(riff.node_at(size + 100).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        // Inside `xyz` payload and its pad byte.
+        assert_eq!(riff.node_at(21).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // Too close to the end for a header.
+        assert!(riff.node_at(size - 2).is_err());
+    }
+
+    #[test]
+    fn node_at_in_short_file() {
+        let riff = Riff::new(Cursor::new(b"abcd\x02\0\0\0xy".to_vec())).unwrap();
+        assert_eq!(payload(riff.node_at(0).unwrap()), (ABCD, 2, 8, b"xy".to_vec()));
+    }
+}
+
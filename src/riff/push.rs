@@ -0,0 +1,430 @@
+use std::io;
+use std::cmp;
+use std::mem;
+
+use fourcc::FourCC;
+use deser::Deser;
+
+use super::{Endian, Ds64, ListHeader, FormHeader, LIST, DS64, SIZE_PLACEHOLDER};
+use super::{list_payload_size, resolve_size, chunk_too_big, list_too_big};
+
+/// Event produced by `PushParser`.
+#[derive(Clone, Debug)]
+pub enum PushEvent<'d> {
+    /// Top level form header.
+    /// For `RF64` with placeholder size the real size comes with `Ds64` event.
+    Riff(ListHeader),
+    ListStart(ListHeader),
+    /// End of list or form.
+    ListEnd(FourCC),
+    ChunkHeader(FourCC, u64),
+    /// Piece of chunk payload, borrowed from pushed buffer.
+    ChunkData(&'d [u8]),
+    /// Parsed `ds64` chunk of `RF64`/`BW64` form, emitted after its payload.
+    Ds64(Ds64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Form,
+    Header,
+    ListType(u64),
+    Data(u64),
+    Pad,
+    Skip(u64),
+    Done,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct OpenList {
+    fcc: FourCC,
+    end: u64,
+    pad: bool,
+}
+
+/// RIFF parser which never performs I/O.
+///
+/// Caller pushes buffers of any size and iterates over produced events.
+/// Header bytes split across buffers are kept internally.
+#[derive(Clone, Debug)]
+pub struct PushParser {
+    state: State,
+    header: [u8; 12],
+    header_len: usize,
+    pos: u64,
+    lists: Vec<OpenList>,
+    chunk_pad: bool,
+    endian: Endian,
+    form_start: u64,
+    form: Option<FormHeader>,
+    ds64: Option<Ds64>,
+    ds64_buf: Option<Vec<u8>>,
+}
+
+impl PushParser {
+    pub fn new() -> Self {
+        PushParser {
+            state: State::Form,
+            header: [0; 12],
+            header_len: 0,
+            pos: 0,
+            lists: vec![],
+            chunk_pad: false,
+            endian: Endian::Little,
+            form_start: 0,
+            form: None,
+            ds64: None,
+            ds64_buf: None,
+        }
+    }
+
+    /// Push next buffer. All of it is consumed while returned iterator is drained.
+    pub fn push<'p, 'd>(&'p mut self, input: &'d [u8]) -> PushEvents<'p, 'd> {
+        PushEvents {
+            parser: self,
+            input: input,
+        }
+    }
+
+    /// Number of bytes pushed and consumed so far.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Number of currently open lists, including top level form.
+    pub fn depth(&self) -> usize {
+        self.lists.len()
+    }
+
+    /// Parser stopped, either after error or after non-RIFF data at top level.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    fn step<'d>(&mut self, input: &mut &'d [u8]) -> io::Result<Option<PushEvent<'d>>> {
+        loop {
+            match self.state {
+                State::Header => {
+                    let list = match self.lists.last() {
+                        Some(&list) => list,
+                        None => {
+                            self.state = State::Form;
+                            continue;
+                        }
+                    };
+                    let left = list.end - self.pos;
+                    if left == 0 {
+                        return Ok(Some(self.end_list()));
+                    }
+                    if self.header_len == 0 && left < mem::size_of::<(FourCC, u32)>() as u64 {
+                        self.state = State::Skip(left);
+                        continue;
+                    }
+                }
+                State::Data(0) => {
+                    self.state = if self.chunk_pad { State::Pad } else { State::Header };
+                    if let Some(data) = self.ds64_buf.take() {
+                        let ds64 = Ds64::deser(&mut &data[..])?;
+                        if self.lists[0].end == u64::max_value() {
+                            let form = self.form.unwrap();
+                            self.lists[0].end = self.form_start + form.payload_size(Some(&ds64))?;
+                        }
+                        self.ds64 = Some(ds64.clone());
+                        return Ok(Some(PushEvent::Ds64(ds64)));
+                    }
+                    continue;
+                }
+                State::Pad => {
+                    if self.lists.last().map_or(false, |list| list.end == self.pos) {
+                        self.state = State::Header;
+                        continue;
+                    }
+                }
+                State::Skip(0) => {
+                    self.state = State::Header;
+                    let list = self.lists.last().cloned().unwrap();
+                    if list.end == self.pos {
+                        return Ok(Some(self.end_list()));
+                    }
+                    continue;
+                }
+                State::Done => return Ok(None),
+                _ => {}
+            }
+
+            if input.is_empty() {
+                return Ok(None);
+            }
+
+            match self.state {
+                State::Form => {
+                    if !self.gather(input, 12) {
+                        continue;
+                    }
+                    return self.read_form();
+                }
+                State::Header => {
+                    if !self.gather(input, 8) {
+                        continue;
+                    }
+                    return self.read_header();
+                }
+                State::ListType(size) => {
+                    if !self.gather(input, 4) {
+                        continue;
+                    }
+                    self.header_len = 0;
+                    let fcc = FourCC::from_slice(&self.header[..4]).unwrap();
+                    let left = self.lists.last().unwrap().end - self.pos;
+                    if size > left {
+                        return Err(list_too_big(size, left));
+                    }
+                    let header = self.start_list(LIST, fcc, size);
+                    self.state = State::Header;
+                    return Ok(Some(PushEvent::ListStart(header)));
+                }
+                State::Data(left) => {
+                    let count = cmp::min(left, input.len() as u64) as usize;
+                    let (data, rest) = input.split_at(count);
+                    *input = rest;
+                    self.pos += count as u64;
+                    self.state = State::Data(left - count as u64);
+                    if let Some(ref mut buf) = self.ds64_buf {
+                        buf.extend_from_slice(data);
+                    }
+                    return Ok(Some(PushEvent::ChunkData(data)));
+                }
+                State::Pad => {
+                    *input = &input[1..];
+                    self.pos += 1;
+                    self.state = State::Header;
+                }
+                State::Skip(left) => {
+                    let count = cmp::min(left, input.len() as u64) as usize;
+                    *input = &input[count..];
+                    self.pos += count as u64;
+                    self.state = State::Skip(left - count as u64);
+                }
+                State::Done => return Ok(None),
+            }
+        }
+    }
+
+    fn read_form<'d>(&mut self) -> io::Result<Option<PushEvent<'d>>> {
+        self.header_len = 0;
+        let form = match FormHeader::parse(&self.header) {
+            Some(form) => form,
+            None => {
+                self.state = State::Done;
+                return Ok(None);
+            }
+        };
+        self.endian = form.endian;
+        self.ds64 = None;
+        self.form_start = self.pos;
+        self.form = Some(form);
+        let header = if form.is_rf64() && form.size == SIZE_PLACEHOLDER {
+            // Real size is known once `ds64` payload arrives.
+            let header = self.start_list(form.id, form.fcc, SIZE_PLACEHOLDER as u64);
+            self.lists[0].end = u64::max_value();
+            header
+        } else {
+            let size = form.payload_size(None)?;
+            self.start_list(form.id, form.fcc, size)
+        };
+        self.state = State::Header;
+        Ok(Some(PushEvent::Riff(header)))
+    }
+
+    fn read_header<'d>(&mut self) -> io::Result<Option<PushEvent<'d>>> {
+        self.header_len = 0;
+        let id = FourCC::from_slice(&self.header[..4]).unwrap();
+        let size = self.endian.read_u32(&mut &self.header[4..8])?;
+        let left = self.lists.last().unwrap().end - self.pos;
+        if id == LIST {
+            if left < mem::size_of::<FourCC>() as u64 {
+                self.state = State::Skip(left);
+                return Ok(None);
+            }
+            let size = list_payload_size(resolve_size(LIST, size, self.ds64.as_ref())?)?;
+            self.state = State::ListType(size);
+            Ok(None)
+        } else {
+            let size = resolve_size(id, size, self.ds64.as_ref())?;
+            if size > left {
+                return Err(chunk_too_big(size, left));
+            }
+            // `ds64` must be the first chunk of the form.
+            // Its buffer grows as data is pushed, declared size is not trusted.
+            if self.form.map_or(false, |form| form.is_rf64()) && id == DS64 && self.pos - 8 == self.form_start {
+                self.ds64_buf = Some(vec![]);
+            }
+            self.chunk_pad = size % 2 == 1;
+            self.state = State::Data(size);
+            Ok(Some(PushEvent::ChunkHeader(id, size)))
+        }
+    }
+
+    fn start_list(&mut self, id: FourCC, fcc: FourCC, size: u64) -> ListHeader {
+        let end = self.pos + size;
+        self.lists.push(OpenList {
+            fcc: fcc,
+            end: end,
+            pad: size % 2 == 1,
+        });
+        ListHeader {
+            id: id,
+            fcc: fcc,
+            size: size,
+            endian: self.endian,
+        }
+    }
+
+    fn end_list<'d>(&mut self) -> PushEvent<'d> {
+        let list = self.lists.pop().unwrap();
+        self.state = if list.pad { State::Pad } else { State::Header };
+        PushEvent::ListEnd(list.fcc)
+    }
+
+    /// Collect header bytes until `need` are available.
+    fn gather(&mut self, input: &mut &[u8], need: usize) -> bool {
+        let count = cmp::min(need - self.header_len, input.len());
+        self.header[self.header_len..self.header_len + count].copy_from_slice(&input[..count]);
+        *input = &input[count..];
+        self.header_len += count;
+        self.pos += count as u64;
+        self.header_len == need
+    }
+}
+
+impl Default for PushParser {
+    fn default() -> Self {
+        PushParser::new()
+    }
+}
+
+/// Events produced from one pushed buffer.
+#[derive(Debug)]
+pub struct PushEvents<'p, 'd> {
+    parser: &'p mut PushParser,
+    input: &'d [u8],
+}
+
+impl<'p, 'd> PushEvents<'p, 'd> {
+    /// Part of pushed buffer not consumed yet.
+    pub fn remaining(&self) -> &'d [u8] {
+        self.input
+    }
+}
+
+impl<'p, 'd> Iterator for PushEvents<'p, 'd> {
+    type Item = io::Result<PushEvent<'d>>;
+
+    fn next(&mut self) -> Option<io::Result<PushEvent<'d>>> {
+        loop {
+            let before = self.input.len();
+            let state = self.parser.state;
+            match self.parser.step(&mut self.input) {
+                Ok(Some(event)) => return Some(Ok(event)),
+                Ok(None) => {
+                    // Header consumed without event, keep going while there is progress.
+                    if self.input.len() == before && self.parser.state == state {
+                        return None;
+                    }
+                }
+                Err(err) => {
+                    self.parser.state = State::Done;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{WriteBytesExt, LittleEndian};
+
+    use fourcc::FourCC;
+    use riff::{RF64, DS64, SIZE_PLACEHOLDER};
+    use super::{PushParser, PushEvent};
+
+    const FILE: &'static [u8] = b"RIFF\x26\0\0\0TEST\
+                                  abcd\x03\0\0\0xyz\0\
+                                  LIST\x0e\0\0\0INFOefgh\x02\0\0\0uv";
+
+    /// Feed `data` in pieces of `step` bytes, merging consecutive data events.
+    fn events(data: &[u8], step: usize) -> Vec<String> {
+        let mut parser = PushParser::new();
+        let mut result: Vec<String> = vec![];
+        for piece in data.chunks(step) {
+            for event in parser.push(piece) {
+                let text = match event.unwrap() {
+                    PushEvent::Riff(header) => format!("riff {} {} {}", header.id, header.fcc, header.size),
+                    PushEvent::ListStart(header) => format!("start {} {}", header.fcc, header.size),
+                    PushEvent::ListEnd(fcc) => format!("end {}", fcc),
+                    PushEvent::ChunkHeader(fcc, size) => format!("chunk {} {}", fcc, size),
+                    PushEvent::ChunkData(data) => {
+                        let text = String::from_utf8_lossy(data).into_owned();
+                        match result.last_mut() {
+                            Some(last) if last.starts_with("data ") => {
+                                last.push_str(&text);
+                                continue;
+                            }
+                            _ => format!("data {}", text),
+                        }
+                    }
+                    PushEvent::Ds64(ds64) => format!("ds64 {}", ds64.riff_size),
+                };
+                result.push(text);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn split_anywhere() {
+        let expected = events(FILE, FILE.len());
+        assert_eq!(expected, vec!["riff RIFF TEST 34", "chunk abcd 3", "data xyz", "start INFO 10",
+                                  "chunk efgh 2", "data uv", "end INFO", "end TEST"]);
+        for step in 1..FILE.len() {
+            assert_eq!(events(FILE, step), expected, "step {}", step);
+        }
+    }
+
+    #[test]
+    fn rf64_size_from_ds64() {
+        let mut data = vec![];
+        data.extend_from_slice(&RF64.0);
+        data.write_u32::<LittleEndian>(SIZE_PLACEHOLDER).unwrap();
+        data.extend_from_slice(b"TEST");
+        data.extend_from_slice(&DS64.0);
+        data.write_u32::<LittleEndian>(28).unwrap();
+        data.write_u64::<LittleEndian>(4 + 36 + 10).unwrap();
+        data.extend_from_slice(&[0; 20]);
+        data.extend_from_slice(b"abcd\x02\0\0\0xy");
+        // Not part of the form.
+        data.extend_from_slice(b"JUNK");
+        let events = events(&data, 5);
+        assert_eq!(&events[..2], &["riff RF64 TEST 4294967295", "chunk ds64 28"]);
+        assert!(events.contains(&"ds64 50".to_string()));
+        assert_eq!(&events[events.len() - 3..], &["chunk abcd 2", "data xy", "end TEST"]);
+    }
+
+    #[test]
+    fn huge_ds64_size() {
+        let mut data = vec![];
+        data.extend_from_slice(&RF64.0);
+        data.write_u32::<LittleEndian>(SIZE_PLACEHOLDER).unwrap();
+        data.extend_from_slice(b"TEST");
+        data.extend_from_slice(&DS64.0);
+        data.write_u32::<LittleEndian>(0xFFFFFFF0).unwrap();
+        let mut parser = PushParser::new();
+        let events = parser.push(&data).collect::<Vec<_>>();
+        assert_eq!(events.len(), 2);
+        match events[1] {
+            Ok(PushEvent::ChunkHeader(fcc, size)) => assert_eq!((fcc, size), (FourCC::from_str("ds64").unwrap(), 0xFFFFFFF0)),
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::fmt::Debug;
+use std::io::{self, Read, Seek};
+
+use fourcc::FourCC;
+
+use super::{Node, List, Riff};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Any,
+    List,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    kind: Kind,
+    fcc: Option<FourCC>,
+    index: Option<usize>,
+}
+
+impl Segment {
+    fn matches<'a, T>(&self, node: &Node<'a, T>) -> bool
+        where T: 'a + Read + Seek + Debug
+    {
+        let kind = match *node {
+            Node::List(_) => true,
+            Node::Chunk(_) => self.kind == Kind::Any,
+        };
+        kind && self.fcc.map_or(true, |fcc| fcc == node.fourcc())
+    }
+}
+
+/// Parsed path into RIFF tree.
+///
+/// Segments are separated by `/`. Each segment is a FourCC of a chunk or list type
+/// (short names are padded with spaces), `*` matching anything, or `LIST:xxxx`
+/// matching only lists. Optional `[n]` suffix selects n-th match (0-based)
+/// among siblings. First segment matches form type of top level forms.
+///
+/// Examples: `"AVI /hdrl/strl[1]/strf"`, `"*/LIST:INFO/INAM"`.
+#[derive(Clone, Debug)]
+pub struct Query {
+    segments: Vec<Segment>,
+}
+
+impl Query {
+    pub fn parse(path: &str) -> io::Result<Query> {
+        let segments = path.split('/').map(parse_segment).collect::<io::Result<Vec<_>>>()?;
+        Ok(Query { segments: segments })
+    }
+}
+
+fn invalid(path: &str, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid path segment {:?}: {}", path, reason))
+}
+
+fn parse_segment(segment: &str) -> io::Result<Segment> {
+    let (name, index) = match segment.find('[') {
+        Some(open) => {
+            if !segment.ends_with(']') {
+                return Err(invalid(segment, "unclosed index"));
+            }
+            let index = segment[open + 1..segment.len() - 1].parse().map_err(|_| invalid(segment, "bad index"))?;
+            (&segment[..open], Some(index))
+        }
+        None => (segment, None),
+    };
+    let (kind, name) = if name.starts_with("LIST:") {
+        (Kind::List, &name[5..])
+    } else {
+        (Kind::Any, name)
+    };
+    let fcc = match name {
+        "*" => None,
+        "" => return Err(invalid(segment, "empty name")),
+        name if name.len() > 4 => return Err(invalid(segment, "name is longer than 4 bytes")),
+        name => {
+            let mut fcc = [b' '; 4];
+            fcc[..name.len()].copy_from_slice(name.as_bytes());
+            Some(FourCC(fcc))
+        }
+    };
+    Ok(Segment {
+        kind: kind,
+        fcc: fcc,
+        index: index,
+    })
+}
+
+/// Match `segments` against `nodes`, descending into matched lists.
+fn collect<'a, T, I>(nodes: I, segments: &[Segment], first: bool, out: &mut Vec<Node<'a, T>>) -> io::Result<()>
+    where T: 'a + Read + Seek + Debug,
+          I: Iterator<Item = io::Result<Node<'a, T>>>
+{
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+    let mut count = 0;
+    for node in nodes {
+        let node = node?;
+        if !segment.matches(&node) {
+            continue;
+        }
+        let this = count;
+        count += 1;
+        if segment.index.map_or(false, |index| index != this) {
+            continue;
+        }
+        if rest.is_empty() {
+            out.push(node);
+        } else if let Node::List(mut list) = node {
+            collect(list.iter(), rest, first, out)?;
+        }
+        if segment.index.is_some() || (first && !out.is_empty()) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl<T> Riff<T>
+    where T: Read + Seek + Debug
+{
+    /// First node matching `path`. See `Query` for path syntax.
+    pub fn find<'a>(&'a mut self, path: &str) -> io::Result<Option<Node<'a, T>>> {
+        let query = Query::parse(path)?;
+        let mut out = vec![];
+        collect(self.iter().map(|list| list.map(Node::List)), &query.segments, true, &mut out)?;
+        Ok(out.into_iter().next())
+    }
+
+    /// All nodes matching `path` in file order.
+    pub fn find_all<'a>(&'a mut self, path: &str) -> io::Result<Vec<Node<'a, T>>> {
+        let query = Query::parse(path)?;
+        let mut out = vec![];
+        collect(self.iter().map(|list| list.map(Node::List)), &query.segments, false, &mut out)?;
+        Ok(out)
+    }
+}
+
+impl<'a, T> List<'a, T>
+    where T: 'a + Read + Seek + Debug
+{
+    /// First node matching `path` relative to this list.
+    pub fn find(&mut self, path: &str) -> io::Result<Option<Node<'a, T>>> {
+        let query = Query::parse(path)?;
+        let mut out = vec![];
+        collect(self.iter(), &query.segments, true, &mut out)?;
+        Ok(out.into_iter().next())
+    }
+
+    /// All nodes matching `path` relative to this list.
+    pub fn find_all(&mut self, path: &str) -> io::Result<Vec<Node<'a, T>>> {
+        let query = Query::parse(path)?;
+        let mut out = vec![];
+        collect(self.iter(), &query.segments, false, &mut out)?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use fourcc::FourCC;
+    use riff::{Riff, Node, RiffWriter};
+    use super::Query;
+
+    fn fcc(name: &str) -> FourCC {
+        FourCC::from_str(name).unwrap()
+    }
+
+    fn file() -> Vec<u8> {
+        let mut writer = RiffWriter::new(Cursor::new(vec![]));
+        writer.begin_riff(fcc("AVI ")).unwrap();
+        writer.begin_list(fcc("hdrl")).unwrap();
+        for data in &[b"s0", b"s1"] {
+            writer.begin_list(fcc("strl")).unwrap();
+            writer.write_chunk(fcc("strh"), b"h").unwrap();
+            writer.write_chunk(fcc("strf"), *data).unwrap();
+            writer.end().unwrap();
+        }
+        writer.end().unwrap();
+        writer.begin_list(fcc("INFO")).unwrap();
+        writer.write_chunk(fcc("INAM"), b"name").unwrap();
+        writer.end().unwrap();
+        // Chunk named like a list type, only matched without `LIST:`.
+        writer.write_chunk(fcc("INFO"), b"x").unwrap();
+        writer.end().unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn payload(node: Node<Cursor<Vec<u8>>>) -> String {
+        let mut chunk = node.chunk_or(()).unwrap();
+        let mut data = String::new();
+        chunk.read().read_to_string(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn indexed_path() {
+        let mut riff = Riff::new(Cursor::new(file())).unwrap();
+        let node = riff.find("AVI /hdrl/strl[1]/strf").unwrap().unwrap();
+        assert_eq!(payload(node), "s1");
+        assert!(riff.find("AVI /hdrl/strl[2]/strf").unwrap().is_none());
+        assert!(riff.find("WAVE/hdrl").unwrap().is_none());
+    }
+
+    #[test]
+    fn wildcards_and_kinds() {
+        let mut riff = Riff::new(Cursor::new(file())).unwrap();
+        let all = riff.find_all("*/hdrl/strl/strf").unwrap();
+        assert_eq!(all.into_iter().map(payload).collect::<Vec<_>>(), vec!["s0", "s1"]);
+        assert_eq!(payload(riff.find("*/LIST:INFO/INAM").unwrap().unwrap()), "name");
+        assert_eq!(riff.find_all("AVI /INFO").unwrap().len(), 2);
+        assert_eq!(riff.find_all("AVI /LIST:INFO").unwrap().len(), 1);
+        let mut form = riff.iter().next().unwrap().unwrap();
+        assert_eq!(payload(form.find("hdrl/*/strh").unwrap().unwrap()), "h");
+    }
+
+    #[test]
+    fn bad_paths() {
+        assert!(Query::parse("AVI /strl[1").is_err());
+        assert!(Query::parse("AVI /strl[x]").is_err());
+        assert!(Query::parse("AVI //strl").is_err());
+        assert!(Query::parse("AVI /hdrlx").is_err());
+        assert!(Query::parse("AVI /LIST:*[0]").is_ok());
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::{self, Read};
+use std::fmt::Debug;
+
+use byteorder::{ReadBytesExt, LittleEndian};
+
+use fourcc::FourCC;
+use deser::Deser;
+
+pub const RF64: FourCC = FourCC([b'R', b'F', b'6', b'4']);
+pub const BW64: FourCC = FourCC([b'B', b'W', b'6', b'4']);
+pub const DS64: FourCC = FourCC([b'd', b's', b'6', b'4']);
+pub const DATA: FourCC = FourCC([b'd', b'a', b't', b'a']);
+
+/// Size value telling that real size is stored in `ds64` chunk.
+pub const SIZE_PLACEHOLDER: u32 = 0xFFFFFFFF;
+
+/// Payload of `ds64` chunk, first chunk of `RF64`/`BW64` forms.
+#[derive(Clone, Debug)]
+pub struct Ds64 {
+    pub riff_size: u64,
+    pub data_size: u64,
+    pub sample_count: u64,
+    pub table: Vec<(FourCC, u64)>,
+}
+
+impl Ds64 {
+    /// 64-bit size for chunk which 32-bit size field holds placeholder.
+    pub fn chunk_size(&self, fcc: FourCC) -> Option<u64> {
+        if fcc == DATA {
+            return Some(self.data_size);
+        }
+        self.table.iter().find(|&&(id, _)| id == fcc).map(|&(_, size)| size)
+    }
+}
+
+impl Deser for Ds64 {
+    fn deser<R: Read+Debug>(read: &mut R) -> io::Result<Ds64> {
+        let riff_size = read.read_u64::<LittleEndian>()?;
+        let data_size = read.read_u64::<LittleEndian>()?;
+        let sample_count = read.read_u64::<LittleEndian>()?;
+        let table_length = read.read_u32::<LittleEndian>()?;
+        // Count comes from the file, so table grows with entries actually read.
+        let mut table = vec![];
+        for _ in 0..table_length {
+            let fcc = FourCC::deser(read)?;
+            let size = read.read_u64::<LittleEndian>()?;
+            table.push((fcc, size));
+        }
+        Ok(Ds64 {
+            riff_size: riff_size,
+            data_size: data_size,
+            sample_count: sample_count,
+            table: table,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use byteorder::{WriteBytesExt, LittleEndian};
+
+    use deser::Deser;
+    use fourcc::FourCC;
+    use riff::{Riff, Node};
+    use super::{Ds64, RF64, DS64, DATA, SIZE_PLACEHOLDER};
+
+    const WAVE: FourCC = FourCC([b'W', b'A', b'V', b'E']);
+    const BIGC: FourCC = FourCC([b'b', b'i', b'g', b'c']);
+
+    fn ds64(riff_size: u64, data_size: u64, table: &[(FourCC, u64)]) -> Vec<u8> {
+        let mut data = vec![];
+        data.write_u64::<LittleEndian>(riff_size).unwrap();
+        data.write_u64::<LittleEndian>(data_size).unwrap();
+        data.write_u64::<LittleEndian>(0).unwrap();
+        data.write_u32::<LittleEndian>(table.len() as u32).unwrap();
+        for &(fcc, size) in table {
+            data.extend_from_slice(&fcc.0);
+            data.write_u64::<LittleEndian>(size).unwrap();
+        }
+        data
+    }
+
+    fn chunk(data: &mut Vec<u8>, fcc: FourCC, size: u32, payload: &[u8]) {
+        data.extend_from_slice(&fcc.0);
+        data.write_u32::<LittleEndian>(size).unwrap();
+        data.extend_from_slice(payload);
+    }
+
+    #[test]
+    fn deser_table() {
+        let data = ds64(100, 50, &[(BIGC, 7)]);
+        let ds64 = Ds64::deser(&mut &data[..]).unwrap();
+        assert_eq!((ds64.riff_size, ds64.data_size), (100, 50));
+        assert_eq!(ds64.chunk_size(DATA), Some(50));
+        assert_eq!(ds64.chunk_size(BIGC), Some(7));
+        assert_eq!(ds64.chunk_size(WAVE), None);
+    }
+
+    #[test]
+    fn huge_table_count() {
+        let mut data = ds64(0, 0, &[]);
+        let len = data.len();
+        (&mut data[len - 4..]).write_u32::<LittleEndian>(u32::max_value()).unwrap();
+        let err = Ds64::deser(&mut &data[..]).unwrap_err();
+        assert_eq!(err.kind(), ::std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn placeholder_sizes() {
+        let table = ds64(0, 3, &[(BIGC, 2)]);
+        let mut body = vec![];
+        chunk(&mut body, DS64, table.len() as u32, &table);
+        chunk(&mut body, DATA, SIZE_PLACEHOLDER, b"abc\0");
+        chunk(&mut body, BIGC, SIZE_PLACEHOLDER, b"xy");
+        let riff_size = 4 + body.len() as u64;
+        (&mut body[8..16]).write_u64::<LittleEndian>(riff_size).unwrap();
+        let mut data = vec![];
+        chunk(&mut data, RF64, SIZE_PLACEHOLDER, &WAVE.0);
+        data.extend_from_slice(&body);
+
+        let mut riff = Riff::new(Cursor::new(data)).unwrap();
+        let mut form = riff.iter().next().unwrap().unwrap();
+        assert_eq!(form.fourcc(), WAVE);
+        assert_eq!(form.ds64().unwrap().riff_size, riff_size);
+        let mut found = vec![];
+        for node in form.iter() {
+            if let Node::Chunk(mut chunk) = node.unwrap() {
+                let mut payload = vec![];
+                chunk.read().read_to_end(&mut payload).unwrap();
+                found.push((chunk.fourcc(), payload));
+            }
+        }
+        assert_eq!(found[1], (DATA, b"abc".to_vec()));
+        assert_eq!(found[2], (BIGC, b"xy".to_vec()));
+    }
+}
@@ -0,0 +1,680 @@
+use std::fmt::{self, Debug, Formatter};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Arc;
+use std::cmp;
+use std::mem;
+
+use fourcc::FourCC;
+use deser::Deser;
+
+use super::{Endian, Ds64, FormHeader, LIST};
+use super::{round2up, list_payload_size, resolve_size, read_ds64, chunk_too_big, list_too_big};
+
+/// Source of positional reads. Reads don't move any shared cursor,
+/// so one source can be read from several threads at once.
+pub trait ReadAt {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+    fn size(&self) -> io::Result<u64>;
+
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+                Ok(read) => {
+                    let tmp = buf;
+                    buf = &mut tmp[read..];
+                    offset += read as u64;
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl ReadAt for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        ::std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+#[cfg(windows)]
+impl ReadAt for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        ::std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.len() as u64 {
+            return Ok(0);
+        }
+        let data = &self[offset as usize..];
+        let count = cmp::min(buf.len(), data.len());
+        buf[..count].copy_from_slice(&data[..count]);
+        Ok(count)
+    }
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self[..].read_at(buf, offset)
+    }
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+impl<'r, R: ReadAt + ?Sized> ReadAt for &'r R {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for Arc<R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+}
+
+/// Cursor over a range of `ReadAt` source.
+struct Cursor<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    source: &'a R,
+    pos: u64,
+    end: u64,
+}
+
+impl<'a, R> Cursor<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fn amount_left(&self) -> u64 {
+        self.end - cmp::min(self.pos, self.end)
+    }
+    fn skip(&mut self, count: u64) {
+        self.pos = cmp::min(self.pos + count, self.end);
+    }
+}
+
+impl<'a, R> Read for Cursor<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let toread = cmp::min(buf.len() as u64, self.amount_left()) as usize;
+        if toread == 0 {
+            return Ok(0);
+        }
+        let read = self.source.read_at(&mut buf[..toread], self.pos)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<'a, R> Debug for Cursor<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Cursor {{ pos: {:?}, end: {:?} }}", self.pos, self.end)
+    }
+}
+
+//
+
+#[derive(Debug)]
+pub enum NodeAt<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    List(ListAt<'a, R>),
+    Chunk(ChunkAt<'a, R>),
+}
+
+impl<'a, R> Clone for NodeAt<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fn clone(&self) -> Self {
+        match *self {
+            NodeAt::List(ref item) => NodeAt::List(item.clone()),
+            NodeAt::Chunk(ref item) => NodeAt::Chunk(item.clone()),
+        }
+    }
+}
+
+impl<'a, R> NodeAt<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    pub fn fourcc(&self) -> FourCC {
+        match *self {
+            NodeAt::List(ref item) => item.fourcc(),
+            NodeAt::Chunk(ref item) => item.fourcc(),
+        }
+    }
+    pub fn chunk_or<E>(self, error: E) -> Result<ChunkAt<'a, R>, E> {
+        match self {
+            NodeAt::Chunk(chunk) => Ok(chunk),
+            NodeAt::List(_) => Err(error),
+        }
+    }
+    pub fn list_or<E>(self, error: E) -> Result<ListAt<'a, R>, E> {
+        match self {
+            NodeAt::List(list) => Ok(list),
+            NodeAt::Chunk(_) => Err(error),
+        }
+    }
+}
+
+//
+
+/// RIFF file read with positional reads.
+///
+/// Unlike `Riff` it holds no cursor, so nodes are `Send + Sync` when the source is,
+/// and can be read from different threads concurrently.
+pub struct RiffAt<R>
+    where R: ReadAt
+{
+    size: u64,
+    source: R,
+}
+
+impl<R> RiffAt<R>
+    where R: ReadAt
+{
+    pub fn new(source: R) -> io::Result<Self> {
+        let size = source.size()?;
+        Ok(RiffAt {
+            size: size,
+            source: source,
+        })
+    }
+
+    pub fn iter(&self) -> RiffAtIter<R> {
+        RiffAtIter {
+            cursor: Cursor {
+                source: &self.source,
+                pos: 0,
+                end: self.size,
+            },
+        }
+    }
+
+    pub fn release(self) -> R {
+        self.source
+    }
+}
+
+impl<R> Debug for RiffAt<R>
+    where R: ReadAt
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "RiffAt {{ size: {:?} }}", self.size)
+    }
+}
+
+#[derive(Debug)]
+pub struct RiffAtIter<'a, R>
+    where R: 'a + ReadAt
+{
+    cursor: Cursor<'a, R>,
+}
+
+impl<'a, R> RiffAtIter<'a, R>
+    where R: 'a + ReadAt
+{
+    fn read_next(&mut self) -> Option<io::Result<ListAt<'a, R>>> {
+        if self.cursor.amount_left() < mem::size_of::<(FourCC, u32, FourCC)>() as u64 {
+            return None;
+        }
+        let mut buf = [0; 12];
+        if let Err(err) = self.cursor.read_exact(&mut buf) {
+            return Some(Err(err));
+        }
+        match FormHeader::parse(&buf) {
+            Some(header) => Some(self.read_next_riff(header)),
+            None => {
+                self.cursor.pos -= buf.len() as u64;
+                None
+            }
+        }
+    }
+
+    fn read_next_riff(&mut self, header: FormHeader) -> io::Result<ListAt<'a, R>> {
+        let ds64 = if header.is_rf64() {
+            let mut head = Cursor {
+                source: self.cursor.source,
+                pos: self.cursor.pos,
+                end: self.cursor.end,
+            };
+            Some(Arc::new(read_ds64(&mut head)?))
+        } else {
+            None
+        };
+        let size = header.payload_size(ds64.as_ref().map(|ds64| &**ds64))?;
+        if size > self.cursor.amount_left() {
+            return Err(list_too_big(size, self.cursor.amount_left()));
+        }
+        let start = self.cursor.pos;
+        self.cursor.skip(round2up(size));
+        Ok(ListAt {
+            fcc: header.fcc,
+            source: self.cursor.source,
+            start: start,
+            size: size,
+            ds64: ds64,
+            endian: header.endian,
+        })
+    }
+}
+
+impl<'a, R> Iterator for RiffAtIter<'a, R>
+    where R: 'a + ReadAt
+{
+    type Item = io::Result<ListAt<'a, R>>;
+
+    fn next(&mut self) -> Option<io::Result<ListAt<'a, R>>> {
+        self.read_next()
+    }
+}
+
+//
+
+pub struct ListAt<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fcc: FourCC,
+    source: &'a R,
+    start: u64,
+    size: u64,
+    ds64: Option<Arc<Ds64>>,
+    endian: Endian,
+}
+
+impl<'a, R> ListAt<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    /// Iterate over sub-nodes. Each iterator has its own position.
+    pub fn iter(&self) -> ListAtIter<'a, R> {
+        ListAtIter {
+            cursor: Cursor {
+                source: self.source,
+                pos: self.start,
+                end: self.start + self.size,
+            },
+            ds64: self.ds64.clone(),
+            endian: self.endian,
+        }
+    }
+    pub fn fourcc(&self) -> FourCC {
+        self.fcc
+    }
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+    pub fn header_offset(&self) -> u64 {
+        self.start - mem::size_of::<(FourCC, u32, FourCC)>() as u64
+    }
+    pub fn data_offset(&self) -> u64 {
+        self.start
+    }
+    pub fn ds64(&self) -> Option<&Ds64> {
+        self.ds64.as_ref().map(|ds64| &**ds64)
+    }
+}
+
+impl<'a, R> Clone for ListAt<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fn clone(&self) -> Self {
+        ListAt {
+            fcc: self.fcc,
+            source: self.source,
+            start: self.start,
+            size: self.size,
+            ds64: self.ds64.clone(),
+            endian: self.endian,
+        }
+    }
+}
+
+impl<'a, R> Debug for ListAt<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ListAt {{ fcc: {:?}, start: {:?}, size: {:?} }}", self.fcc, self.start, self.size)
+    }
+}
+
+#[derive(Debug)]
+pub struct ListAtIter<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    cursor: Cursor<'a, R>,
+    ds64: Option<Arc<Ds64>>,
+    endian: Endian,
+}
+
+impl<'a, R> ListAtIter<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fn read_next(&mut self) -> Option<io::Result<NodeAt<'a, R>>> {
+        if self.cursor.amount_left() < mem::size_of::<FourCC>() as u64 {
+            return None;
+        }
+
+        match FourCC::deser(&mut self.cursor) {
+            Ok(LIST) => {
+                if self.cursor.amount_left() < mem::size_of::<(FourCC, u32)>() as u64 {
+                    return None;
+                }
+                Some(self.read_list().map(NodeAt::List))
+            }
+            Ok(fcc) => {
+                if self.cursor.amount_left() < mem::size_of::<u32>() as u64 {
+                    return None;
+                }
+                Some(self.read_chunk(fcc).map(NodeAt::Chunk))
+            }
+            Err(err) => Some(Err(err))
+        }
+    }
+    fn read_list(&mut self) -> io::Result<ListAt<'a, R>> {
+        let size = self.read_size(LIST)?;
+        let size = list_payload_size(size)?;
+        let fcc = FourCC::deser(&mut self.cursor)?;
+        if size > self.cursor.amount_left() {
+            return Err(list_too_big(size, self.cursor.amount_left()));
+        }
+        let start = self.cursor.pos;
+        self.cursor.skip(round2up(size));
+        Ok(ListAt {
+            fcc: fcc,
+            source: self.cursor.source,
+            start: start,
+            size: size,
+            ds64: self.ds64.clone(),
+            endian: self.endian,
+        })
+    }
+    fn read_chunk(&mut self, fcc: FourCC) -> io::Result<ChunkAt<'a, R>> {
+        let size = self.read_size(fcc)?;
+        if size > self.cursor.amount_left() {
+            return Err(chunk_too_big(size, self.cursor.amount_left()));
+        }
+        let start = self.cursor.pos;
+        self.cursor.skip(round2up(size));
+        Ok(ChunkAt {
+            fcc: fcc,
+            source: self.cursor.source,
+            start: start,
+            size: size,
+            endian: self.endian,
+        })
+    }
+    fn read_size(&mut self, fcc: FourCC) -> io::Result<u64> {
+        let size = self.endian.read_u32(&mut self.cursor)?;
+        resolve_size(fcc, size, self.ds64.as_ref().map(|ds64| &**ds64))
+    }
+}
+
+impl<'a, R> Iterator for ListAtIter<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    type Item = io::Result<NodeAt<'a, R>>;
+
+    fn next(&mut self) -> Option<io::Result<NodeAt<'a, R>>> {
+        self.read_next()
+    }
+}
+
+//
+
+pub struct ChunkAt<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fcc: FourCC,
+    source: &'a R,
+    start: u64,
+    size: u64,
+    endian: Endian,
+}
+
+impl<'a, R> ChunkAt<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    pub fn fourcc(&self) -> FourCC {
+        self.fcc
+    }
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+    pub fn header_offset(&self) -> u64 {
+        self.start - mem::size_of::<(FourCC, u32)>() as u64
+    }
+    pub fn data_offset(&self) -> u64 {
+        self.start
+    }
+    /// Read payload at `offset` within the chunk.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+        let toread = cmp::min(buf.len() as u64, self.size - offset) as usize;
+        self.source.read_at(&mut buf[..toread], self.start + offset)
+    }
+    /// Reader with its own position. Any number of readers may exist at once.
+    pub fn read(&self) -> ChunkAtReader<'a, R> {
+        ChunkAtReader {
+            cursor: Cursor {
+                source: self.source,
+                pos: self.start,
+                end: self.start + self.size,
+            },
+            start: self.start,
+        }
+    }
+}
+
+impl<'a, R> Clone for ChunkAt<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fn clone(&self) -> Self {
+        ChunkAt {
+            fcc: self.fcc,
+            source: self.source,
+            start: self.start,
+            size: self.size,
+            endian: self.endian,
+        }
+    }
+}
+
+impl<'a, R> Debug for ChunkAt<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ChunkAt {{ fcc: {:?}, start: {:?}, size: {:?} }}", self.fcc, self.start, self.size)
+    }
+}
+
+#[derive(Debug)]
+pub struct ChunkAtReader<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    cursor: Cursor<'a, R>,
+    start: u64,
+}
+
+impl<'a, R> Read for ChunkAtReader<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.cursor.read(buf)
+    }
+}
+
+impl<'a, R> Seek for ChunkAtReader<'a, R>
+    where R: 'a + ReadAt + ?Sized
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let size = self.cursor.end - self.start;
+        let current = self.cursor.pos - self.start;
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => offset_by(current, delta),
+            SeekFrom::End(delta) => offset_by(size, delta),
+        };
+        match pos.and_then(|pos| self.start.checked_add(pos).map(|absolute| (pos, absolute))) {
+            Some((pos, absolute)) => {
+                self.cursor.pos = absolute;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+        }
+    }
+}
+
+/// `base + delta`, `None` if it is negative or overflows.
+fn offset_by(base: u64, delta: i64) -> Option<u64> {
+    if delta >= 0 {
+        base.checked_add(delta as u64)
+    } else {
+        base.checked_sub(delta.wrapping_neg() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom, ErrorKind};
+    use std::thread;
+
+    use fourcc::FourCC;
+    use riff::RiffWriter;
+    use super::{RiffAt, ChunkAt, NodeAt};
+
+    fn assert_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn nodes_are_sync() {
+        assert_sync::<RiffAt<Vec<u8>>>();
+        assert_sync::<RiffAt<::std::fs::File>>();
+        assert_sync::<ChunkAt<'static, Vec<u8>>>();
+    }
+
+    fn file() -> Vec<u8> {
+        let mut writer = RiffWriter::new(::std::io::Cursor::new(vec![]));
+        writer.begin_riff(FourCC::from_str("TEST").unwrap()).unwrap();
+        writer.write_chunk(FourCC::from_str("abcd").unwrap(), b"xyz").unwrap();
+        writer.begin_list(FourCC::from_str("INFO").unwrap()).unwrap();
+        writer.write_chunk(FourCC::from_str("efgh").unwrap(), b"uvw").unwrap();
+        writer.end().unwrap();
+        writer.end().unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn tree_and_offsets() {
+        let data = file();
+        let riff = RiffAt::new(&data[..]).unwrap();
+        let forms = riff.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(forms.len(), 1);
+        assert_eq!((forms[0].fourcc(), forms[0].size(), forms[0].header_offset()), (FourCC::from_str("TEST").unwrap(), 36, 0));
+        let nodes = forms[0].iter().collect::<Result<Vec<_>, _>>().unwrap();
+        let chunk = nodes[0].clone().chunk_or(()).unwrap();
+        assert_eq!((chunk.header_offset(), chunk.data_offset(), chunk.size()), (12, 20, 3));
+        let mut buf = [0; 8];
+        assert_eq!(chunk.read_at(&mut buf, 1).unwrap(), 2);
+        assert_eq!(&buf[..2], b"yz");
+        match nodes[1] {
+            NodeAt::List(ref list) => {
+                assert_eq!((list.fourcc(), list.header_offset()), (FourCC::from_str("INFO").unwrap(), 24));
+                assert_eq!(list.iter().count(), 1);
+            }
+            _ => panic!("expected list"),
+        }
+    }
+
+    #[test]
+    fn concurrent_readers() {
+        let riff = RiffAt::new(file()).unwrap();
+        let form = riff.iter().next().unwrap().unwrap();
+        let mut chunks = vec![];
+        for node in form.iter() {
+            match node.unwrap() {
+                NodeAt::Chunk(chunk) => chunks.push(chunk),
+                NodeAt::List(list) => chunks.extend(list.iter().map(|node| node.unwrap().chunk_or(()).unwrap())),
+            }
+        }
+        assert_eq!(chunks.len(), 2);
+        let texts = thread::scope(|scope| {
+            let handles = (0..8).map(|i| {
+                let chunk = &chunks[i % 2];
+                scope.spawn(move || {
+                    let mut text = String::new();
+                    for _ in 0..100 {
+                        text.clear();
+                        chunk.read().read_to_string(&mut text).unwrap();
+                    }
+                    text
+                })
+            }).collect::<Vec<_>>();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+        });
+        for (i, text) in texts.iter().enumerate() {
+            assert_eq!(text, ["xyz", "uvw"][i % 2]);
+        }
+    }
+
+    #[test]
+    fn seek_limits() {
+        let data = file();
+        let riff = RiffAt::new(&data[..]).unwrap();
+        let form = riff.iter().next().unwrap().unwrap();
+        let chunk = form.iter().next().unwrap().unwrap().chunk_or(()).unwrap();
+        let mut reader = chunk.read();
+        assert_eq!(reader.seek(SeekFrom::End(-1)).unwrap(), 2);
+        assert_eq!(reader.seek(SeekFrom::Start(1 << 63)).unwrap(), 1 << 63);
+        assert_eq!(reader.read(&mut [0; 4]).unwrap(), 0);
+        assert_eq!(reader.seek(SeekFrom::Start(u64::max_value())).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(reader.seek(SeekFrom::Current(i64::max_value())).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(reader.seek(SeekFrom::End(-4)).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(reader.seek(SeekFrom::Current(i64::min_value())).unwrap(), 0);
+        assert_eq!(reader.seek(SeekFrom::Start(1)).unwrap(), 1);
+        assert_eq!(reader.seek(SeekFrom::Current(i64::min_value())).unwrap_err().kind(), ErrorKind::InvalidInput);
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "yz");
+    }
+
+    #[test]
+    fn chunk_past_end() {
+        let data = b"RIFF\x10\0\0\0TESTabcd\x20\0\0\0xyzw".to_vec();
+        let riff = RiffAt::new(data).unwrap();
+        let form = riff.iter().next().unwrap().unwrap();
+        let err = form.iter().next().unwrap().unwrap_err();
+        assert!(err.to_string().starts_with("Chunk is too big"));
+    }
+}
@@ -0,0 +1,307 @@
+use std::io;
+use std::cmp;
+use std::mem;
+use std::rc::Rc;
+
+use fourcc::FourCC;
+use deser::Deser;
+
+use super::{Endian, Ds64, FormHeader, LIST};
+use super::{round2up, list_payload_size, resolve_size, read_ds64, chunk_too_big};
+
+/// Split `size` bytes of payload from `rest` and skip padding byte after it.
+fn split_payload<'a>(rest: &mut &'a [u8], size: u64) -> io::Result<&'a [u8]> {
+    if size > rest.len() as u64 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("Unexpected end of slice when taking {} of {}", size, rest.len())));
+    }
+    let payload = &rest[..size as usize];
+    let skip = cmp::min(round2up(size), rest.len() as u64) as usize;
+    *rest = &rest[skip..];
+    Ok(payload)
+}
+
+#[derive(Debug, Clone)]
+pub enum NodeSlice<'a> {
+    List(ListSlice<'a>),
+    Chunk(ChunkSlice<'a>),
+}
+
+impl<'a> NodeSlice<'a> {
+    pub fn fourcc(&self) -> FourCC {
+        match *self {
+            NodeSlice::List(ref item) => item.fourcc(),
+            NodeSlice::Chunk(ref item) => item.fourcc(),
+        }
+    }
+    pub fn chunk_or<E>(self, error: E) -> Result<ChunkSlice<'a>, E> {
+        self.chunk_or_else(|_| error)
+    }
+    pub fn chunk_or_else<E, F>(self, f: F) -> Result<ChunkSlice<'a>, E> where F: FnOnce(ListSlice<'a>) -> E {
+        match self {
+            NodeSlice::Chunk(chunk) => Ok(chunk),
+            NodeSlice::List(list) => Err(f(list))
+        }
+    }
+    pub fn list_or<E>(self, error: E) -> Result<ListSlice<'a>, E> {
+        self.list_or_else(|_| error)
+    }
+    pub fn list_or_else<E, F>(self, f: F) -> Result<ListSlice<'a>, E> where F: FnOnce(ChunkSlice<'a>) -> E {
+        match self {
+            NodeSlice::List(list) => Ok(list),
+            NodeSlice::Chunk(chunk) => Err(f(chunk))
+        }
+    }
+}
+
+//
+
+/// RIFF file held in memory. Payloads are borrowed from the slice without copying.
+#[derive(Debug, Clone, Copy)]
+pub struct RiffSlice<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> RiffSlice<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        RiffSlice { data: data }
+    }
+
+    pub fn iter(&self) -> RiffSliceIter<'a> {
+        RiffSliceIter { rest: self.data }
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RiffSliceIter<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> RiffSliceIter<'a> {
+    fn read_next(&mut self) -> Option<io::Result<ListSlice<'a>>> {
+        let header = FormHeader::parse(self.rest)?;
+        self.rest = &self.rest[mem::size_of::<(FourCC, u32, FourCC)>()..];
+        Some(self.read_next_riff(header))
+    }
+
+    fn read_next_riff(&mut self, header: FormHeader) -> io::Result<ListSlice<'a>> {
+        let ds64 = if header.is_rf64() {
+            let mut head = self.rest;
+            Some(Rc::new(read_ds64(&mut head)?))
+        } else {
+            None
+        };
+        let size = header.payload_size(ds64.as_ref().map(|ds64| &**ds64))?;
+        let data = split_payload(&mut self.rest, size)?;
+        Ok(ListSlice {
+            fcc: header.fcc,
+            data: data,
+            ds64: ds64,
+            endian: header.endian,
+        })
+    }
+}
+
+impl<'a> Iterator for RiffSliceIter<'a> {
+    type Item = io::Result<ListSlice<'a>>;
+
+    fn next(&mut self) -> Option<io::Result<ListSlice<'a>>> {
+        self.read_next()
+    }
+}
+
+//
+
+#[derive(Debug, Clone)]
+pub struct ListSlice<'a> {
+    fcc: FourCC,
+    data: &'a [u8],
+    ds64: Option<Rc<Ds64>>,
+    endian: Endian,
+}
+
+impl<'a> ListSlice<'a> {
+    pub(super) fn from_parts(fcc: FourCC, data: &'a [u8], ds64: Option<Rc<Ds64>>, endian: Endian) -> Self {
+        ListSlice {
+            fcc: fcc,
+            data: data,
+            ds64: ds64,
+            endian: endian,
+        }
+    }
+    pub fn iter(&self) -> ListSliceIter<'a> {
+        ListSliceIter {
+            rest: self.data,
+            ds64: self.ds64.clone(),
+            endian: self.endian,
+        }
+    }
+    pub fn fourcc(&self) -> FourCC {
+        self.fcc
+    }
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+    /// Raw payload of the list after list type.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+    pub fn ds64(&self) -> Option<&Ds64> {
+        self.ds64.as_ref().map(|ds64| &**ds64)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ListSliceIter<'a> {
+    rest: &'a [u8],
+    ds64: Option<Rc<Ds64>>,
+    endian: Endian,
+}
+
+impl<'a> ListSliceIter<'a> {
+    fn read_next(&mut self) -> Option<io::Result<NodeSlice<'a>>> {
+        if self.rest.len() < mem::size_of::<FourCC>() {
+            return None;
+        }
+
+        match FourCC::deser(&mut self.rest) {
+            Ok(LIST) => {
+                if self.rest.len() < mem::size_of::<(FourCC, u32)>() {
+                    return None;
+                }
+                Some(self.read_list().map(NodeSlice::List))
+            }
+            Ok(fcc) => {
+                if self.rest.len() < mem::size_of::<u32>() {
+                    return None;
+                }
+                Some(self.read_chunk(fcc).map(NodeSlice::Chunk))
+            }
+            Err(err) => Some(Err(err))
+        }
+    }
+    fn read_list(&mut self) -> io::Result<ListSlice<'a>> {
+        let size = self.read_size(LIST)?;
+        let size = list_payload_size(size)?;
+        let fcc = FourCC::deser(&mut self.rest)?;
+        let data = split_payload(&mut self.rest, size)?;
+        Ok(ListSlice {
+            fcc: fcc,
+            data: data,
+            ds64: self.ds64.clone(),
+            endian: self.endian,
+        })
+    }
+    fn read_chunk(&mut self, fcc: FourCC) -> io::Result<ChunkSlice<'a>> {
+        let size = self.read_size(fcc)?;
+        if size > self.rest.len() as u64 {
+            return Err(chunk_too_big(size, self.rest.len() as u64));
+        }
+        let data = split_payload(&mut self.rest, size)?;
+        Ok(ChunkSlice {
+            fcc: fcc,
+            data: data,
+            endian: self.endian,
+        })
+    }
+    fn read_size(&mut self, fcc: FourCC) -> io::Result<u64> {
+        let size = self.endian.read_u32(&mut self.rest)?;
+        resolve_size(fcc, size, self.ds64.as_ref().map(|ds64| &**ds64))
+    }
+}
+
+impl<'a> Iterator for ListSliceIter<'a> {
+    type Item = io::Result<NodeSlice<'a>>;
+
+    fn next(&mut self) -> Option<io::Result<NodeSlice<'a>>> {
+        self.read_next()
+    }
+}
+
+//
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkSlice<'a> {
+    fcc: FourCC,
+    data: &'a [u8],
+    endian: Endian,
+}
+
+impl<'a> ChunkSlice<'a> {
+    pub(super) fn from_parts(fcc: FourCC, data: &'a [u8], endian: Endian) -> Self {
+        ChunkSlice {
+            fcc: fcc,
+            data: data,
+            endian: endian,
+        }
+    }
+    pub fn fourcc(&self) -> FourCC {
+        self.fcc
+    }
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+    /// Chunk payload without padding.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use fourcc::FourCC;
+    use super::{RiffSlice, NodeSlice};
+
+    const TEST: FourCC = FourCC([b'T', b'E', b'S', b'T']);
+    const INFO: FourCC = FourCC([b'I', b'N', b'F', b'O']);
+    const ABCD: FourCC = FourCC([b'a', b'b', b'c', b'd']);
+
+    const FILE: &'static [u8] = b"RIFF\x24\0\0\0TEST\
+                                  abcd\x03\0\0\0xyz\0\
+                                  LIST\x0c\0\0\0INFOabcd\0\0\0\0";
+
+    #[test]
+    fn borrows_payloads() {
+        let riff = RiffSlice::new(FILE);
+        let forms = riff.iter().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(forms.len(), 1);
+        assert_eq!(forms[0].fourcc(), TEST);
+        let nodes = forms[0].iter().collect::<io::Result<Vec<_>>>().unwrap();
+        let chunk = nodes[0].clone().chunk_or(()).unwrap();
+        assert_eq!((chunk.fourcc(), chunk.data()), (ABCD, &b"xyz"[..]));
+        assert_eq!(chunk.data().as_ptr(), FILE[20..].as_ptr());
+        let list = nodes[1].clone().list_or(()).unwrap();
+        assert_eq!(list.fourcc(), INFO);
+        match list.iter().next().unwrap().unwrap() {
+            NodeSlice::Chunk(chunk) => assert_eq!((chunk.fourcc(), chunk.size()), (ABCD, 0)),
+            NodeSlice::List(_) => panic!("expected chunk"),
+        }
+    }
+
+    #[test]
+    fn truncated_chunk() {
+        let riff = RiffSlice::new(&FILE[..FILE.len() - 1]);
+        assert!(riff.iter().next().unwrap().is_err());
+        let mut data = FILE.to_vec();
+        data[16] = 0x30;
+        let form = RiffSlice::new(&data).iter().next().unwrap().unwrap();
+        let err = form.iter().next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn stops_at_non_riff() {
+        assert!(RiffSlice::new(b"JUNK\0\0\0\0JUNK").iter().next().is_none());
+    }
+}
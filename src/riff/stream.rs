@@ -0,0 +1,430 @@
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Read, Cursor};
+use std::cmp;
+use std::mem;
+
+use fourcc::FourCC;
+use deser::Deser;
+
+use super::{Endian, Ds64, FormHeader, LIST, DS64};
+use super::{list_payload_size, resolve_size, ds64_size, chunk_too_big, list_too_big};
+
+/// Header of `RIFF`, `RIFX`, `RF64` form or `LIST`.
+#[derive(Clone, Copy, Debug)]
+pub struct ListHeader {
+    /// `RIFF`, `RIFX`, `RF64`, `BW64` or `LIST`.
+    pub id: FourCC,
+    /// Form or list type.
+    pub fcc: FourCC,
+    /// Size of payload after list type.
+    pub size: u64,
+    pub endian: Endian,
+}
+
+/// Event emitted by `RiffReader` in file order.
+#[derive(Debug)]
+pub enum Event<'b, R>
+    where R: 'b + Read
+{
+    ListStart(ListHeader),
+    Chunk(ChunkStream<'b, R>),
+    ListEnd(FourCC),
+}
+
+/// Event without borrow of the reader.
+#[derive(Clone, Copy, Debug)]
+enum Step {
+    ListStart(ListHeader),
+    Chunk(FourCC, u64),
+    ListEnd(FourCC),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct OpenList {
+    fcc: FourCC,
+    end: u64,
+    pad: bool,
+}
+
+/// Forward-only RIFF parser over non-seekable stream.
+///
+/// Chunk payload left unread is skipped when next event is requested.
+pub struct RiffReader<R>
+    where R: Read
+{
+    read: R,
+    pos: u64,
+    lists: Vec<OpenList>,
+    chunk_left: u64,
+    chunk_pad: bool,
+    buffered: Option<Cursor<Vec<u8>>>,
+    pending_ds64: Option<Vec<u8>>,
+    ds64: Option<Ds64>,
+    endian: Endian,
+    done: bool,
+}
+
+impl<R> RiffReader<R>
+    where R: Read
+{
+    pub fn new(read: R) -> Self {
+        RiffReader {
+            read: read,
+            pos: 0,
+            lists: vec![],
+            chunk_left: 0,
+            chunk_pad: false,
+            buffered: None,
+            pending_ds64: None,
+            ds64: None,
+            endian: Endian::Little,
+            done: false,
+        }
+    }
+
+    /// Next event. Not an `Iterator` since chunk events borrow the reader.
+    pub fn next(&mut self) -> Option<io::Result<Event<R>>> {
+        match self.read_next() {
+            Ok(Some(Step::ListStart(header))) => Some(Ok(Event::ListStart(header))),
+            Ok(Some(Step::Chunk(fcc, size))) => {
+                Some(Ok(Event::Chunk(ChunkStream {
+                    fcc: fcc,
+                    size: size,
+                    inner: self,
+                })))
+            }
+            Ok(Some(Step::ListEnd(fcc))) => Some(Ok(Event::ListEnd(fcc))),
+            Ok(None) => None,
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+
+    /// Number of bytes consumed from underlying stream.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Number of currently open lists, including top level form.
+    pub fn depth(&self) -> usize {
+        self.lists.len()
+    }
+
+    /// `ds64` chunk of current `RF64`/`BW64` form.
+    pub fn ds64(&self) -> Option<&Ds64> {
+        self.ds64.as_ref()
+    }
+
+    pub fn release(self) -> R {
+        self.read
+    }
+
+    fn read_next(&mut self) -> io::Result<Option<Step>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.skip_chunk()?;
+
+        if let Some(data) = self.pending_ds64.take() {
+            let size = data.len() as u64;
+            self.buffered = Some(Cursor::new(data));
+            return Ok(Some(Step::Chunk(DS64, size)));
+        }
+
+        let list = match self.lists.last() {
+            Some(&list) => list,
+            None => return self.read_form(),
+        };
+
+        if list.end - self.pos < mem::size_of::<(FourCC, u32)>() as u64 {
+            return self.end_list().map(Some);
+        }
+        let mut header = [0; 8];
+        self.fill(&mut header)?;
+        let id = FourCC::from_slice(&header[..4]).unwrap();
+        let size = self.endian.read_u32(&mut &header[4..])?;
+        if id == LIST {
+            if list.end - self.pos < mem::size_of::<FourCC>() as u64 {
+                return self.end_list().map(Some);
+            }
+            let size = list_payload_size(resolve_size(LIST, size, self.ds64.as_ref())?)?;
+            let fcc = self.read_fourcc()?;
+            if size > list.end - self.pos {
+                return Err(list_too_big(size, list.end - self.pos));
+            }
+            let start = self.pos;
+            Ok(Some(Step::ListStart(self.start_list(LIST, fcc, start, size))))
+        } else {
+            let size = resolve_size(id, size, self.ds64.as_ref())?;
+            if size > list.end - self.pos {
+                return Err(chunk_too_big(size, list.end - self.pos));
+            }
+            self.chunk_left = size;
+            self.chunk_pad = size % 2 == 1;
+            Ok(Some(Step::Chunk(id, size)))
+        }
+    }
+
+    fn read_form(&mut self) -> io::Result<Option<Step>> {
+        let mut bytes = [0; 12];
+        if self.fill_up_to(&mut bytes)? < bytes.len() {
+            self.done = true;
+            return Ok(None);
+        }
+        let header = match FormHeader::parse(&bytes) {
+            Some(header) => header,
+            None => {
+                self.done = true;
+                return Ok(None);
+            }
+        };
+        self.endian = header.endian;
+        self.ds64 = None;
+        if header.is_rf64() {
+            self.ds64 = Some(self.read_ds64()?);
+        }
+        let size = header.payload_size(self.ds64.as_ref())?;
+        // `ds64` chunk is already consumed, but it belongs to the form.
+        let consumed = self.pending_ds64.as_ref().map_or(0, |data| 8 + data.len() as u64 + data.len() as u64 % 2);
+        if size < consumed {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Form is smaller than its ds64 chunk: {}", size)));
+        }
+        let start = self.pos - consumed;
+        Ok(Some(Step::ListStart(self.start_list(header.id, header.fcc, start, size))))
+    }
+
+    /// Read `ds64` chunk ahead to learn form size. It is emitted as regular chunk afterwards.
+    fn read_ds64(&mut self) -> io::Result<Ds64> {
+        let mut header = [0; 8];
+        self.fill(&mut header)?;
+        let size = ds64_size(&header)?;
+        // Size comes from the file, so the buffer grows only with bytes actually read.
+        let mut data = vec![];
+        (&mut self.read).take(size).read_to_end(&mut data)?;
+        self.pos += data.len() as u64;
+        if (data.len() as u64) < size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("ds64 chunk is truncated: {} of {} bytes", data.len(), size)));
+        }
+        if size % 2 == 1 {
+            self.skip(1)?;
+        }
+        let ds64 = Ds64::deser(&mut &data[..])?;
+        self.pending_ds64 = Some(data);
+        Ok(ds64)
+    }
+
+    fn start_list(&mut self, id: FourCC, fcc: FourCC, start: u64, size: u64) -> ListHeader {
+        let end = start + size;
+        self.lists.push(OpenList {
+            fcc: fcc,
+            end: end,
+            pad: size % 2 == 1,
+        });
+        ListHeader {
+            id: id,
+            fcc: fcc,
+            size: size,
+            endian: self.endian,
+        }
+    }
+
+    fn end_list(&mut self) -> io::Result<Step> {
+        let list = self.lists.pop().unwrap();
+        let left = list.end - self.pos;
+        self.skip(left)?;
+        if list.pad {
+            // Padding may be missing at the very end of the stream.
+            let limit = self.lists.last().map_or(1, |parent| cmp::min(1, parent.end - self.pos));
+            self.skip(limit)?;
+        }
+        Ok(Step::ListEnd(list.fcc))
+    }
+
+    fn skip_chunk(&mut self) -> io::Result<()> {
+        self.buffered = None;
+        let left = self.chunk_left + if self.chunk_pad { 1 } else { 0 };
+        let left = match self.lists.last() {
+            Some(list) => cmp::min(left, list.end - self.pos),
+            None => left,
+        };
+        self.chunk_left = 0;
+        self.chunk_pad = false;
+        self.skip(left)?;
+        Ok(())
+    }
+
+    /// Skip up to `count` bytes. Returns number of bytes skipped.
+    fn skip(&mut self, count: u64) -> io::Result<u64> {
+        let skipped = io::copy(&mut (&mut self.read).take(count), &mut io::sink())?;
+        self.pos += skipped;
+        Ok(skipped)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.read.read_exact(buf)?;
+        self.pos += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_fourcc(&mut self) -> io::Result<FourCC> {
+        let mut fcc = [0; 4];
+        self.fill(&mut fcc)?;
+        Ok(FourCC(fcc))
+    }
+
+    fn fill_up_to(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.read.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        self.pos += filled as u64;
+        Ok(filled)
+    }
+}
+
+impl<R> Debug for RiffReader<R>
+    where R: Read
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "RiffReader {{ pos: {:?}, lists: {:?}, chunk_left: {:?} }}", self.pos, self.lists, self.chunk_left)
+    }
+}
+
+/// Payload of a chunk emitted by `RiffReader`, bounded by chunk size.
+pub struct ChunkStream<'b, R>
+    where R: 'b + Read
+{
+    fcc: FourCC,
+    size: u64,
+    inner: &'b mut RiffReader<R>,
+}
+
+impl<'b, R> ChunkStream<'b, R>
+    where R: 'b + Read
+{
+    pub fn fourcc(&self) -> FourCC {
+        self.fcc
+    }
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    pub fn endian(&self) -> Endian {
+        self.inner.endian
+    }
+}
+
+impl<'b, R> Read for ChunkStream<'b, R>
+    where R: 'b + Read
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(ref mut buffered) = self.inner.buffered {
+            return buffered.read(buf);
+        }
+        let toread = cmp::min(buf.len() as u64, self.inner.chunk_left) as usize;
+        if toread == 0 {
+            return Ok(0);
+        }
+        let read = self.inner.read.read(&mut buf[..toread])?;
+        self.inner.pos += read as u64;
+        self.inner.chunk_left -= read as u64;
+        Ok(read)
+    }
+}
+
+impl<'b, R> Debug for ChunkStream<'b, R>
+    where R: 'b + Read
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ChunkStream {{ fcc: {:?}, size: {:?} }}", self.fcc, self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use byteorder::{WriteBytesExt, LittleEndian};
+
+    use fourcc::FourCC;
+    use riff::{RF64, DS64};
+    use super::{RiffReader, Event};
+
+    const TEST: FourCC = FourCC([b'T', b'E', b'S', b'T']);
+
+    /// Events as strings, reading payload of every second chunk only.
+    fn events<R: Read>(read: R) -> Vec<String> {
+        let mut reader = RiffReader::new(read);
+        let mut result = vec![];
+        let mut chunks = 0;
+        while let Some(event) = reader.next() {
+            result.push(match event.unwrap() {
+                Event::ListStart(header) => format!("start {} {} {}", header.id, header.fcc, header.size),
+                Event::ListEnd(fcc) => format!("end {}", fcc),
+                Event::Chunk(mut chunk) => {
+                    chunks += 1;
+                    let mut data = vec![];
+                    if chunks % 2 == 0 {
+                        chunk.read_to_end(&mut data).unwrap();
+                    }
+                    format!("chunk {} {} {:?}", chunk.fourcc(), chunk.size(), String::from_utf8(data).unwrap())
+                }
+            });
+        }
+        result
+    }
+
+    /// Reader returning one byte per call, like a slow pipe.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    const FILE: &'static [u8] = b"RIFF\x32\0\0\0TEST\
+                                  abcd\x03\0\0\0xyz\0\
+                                  LIST\x10\0\0\0INFOefgh\x03\0\0\0uvw\0\
+                                  ijkl\x01\0\0\0q\0";
+
+    #[test]
+    fn events_in_file_order() {
+        let expected = vec![
+            "start RIFF TEST 46",
+            "chunk abcd 3 \"\"",
+            "start LIST INFO 12",
+            "chunk efgh 3 \"uvw\"",
+            "end INFO",
+            "chunk ijkl 1 \"\"",
+            "end TEST",
+        ];
+        assert_eq!(events(FILE), expected);
+        assert_eq!(events(Trickle(FILE)), expected);
+    }
+
+    #[test]
+    fn huge_ds64_size() {
+        let mut data = vec![];
+        data.extend_from_slice(&RF64.0);
+        data.write_u32::<LittleEndian>(u32::max_value()).unwrap();
+        data.extend_from_slice(&TEST.0);
+        data.extend_from_slice(&DS64.0);
+        data.write_u32::<LittleEndian>(0xFFFFFFF0).unwrap();
+        data.extend_from_slice(&[0; 28]);
+        let mut reader = RiffReader::new(&data[..]);
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Write, Seek, SeekFrom};
+
+use byteorder::{WriteBytesExt, LittleEndian};
+
+use fourcc::FourCC;
+
+use super::{LIST, RIFF};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScopeKind {
+    Riff,
+    List,
+    Chunk,
+}
+
+/// Open scope. `start` is the position of the size field.
+#[derive(Clone, Copy, Debug)]
+struct Scope {
+    kind: ScopeKind,
+    start: u64,
+}
+
+/// Writes RIFF trees into a `Write + Seek` stream.
+///
+/// Size fields are written as zero when a scope is opened and patched when
+/// it is closed. Odd-sized payloads get a padding byte, mirroring `round2up`
+/// used when reading.
+pub struct RiffWriter<T>
+    where T: Write + Seek
+{
+    stream: T,
+    scopes: Vec<Scope>,
+}
+
+impl<T> RiffWriter<T>
+    where T: Write + Seek
+{
+    pub fn new(stream: T) -> Self {
+        RiffWriter {
+            stream: stream,
+            scopes: vec![],
+        }
+    }
+
+    /// Open `RIFF` form with given form type. Only allowed at top level.
+    pub fn begin_riff(&mut self, fcc: FourCC) -> io::Result<()> {
+        if !self.scopes.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "RIFF form must be at top level"));
+        }
+        self.open(ScopeKind::Riff, RIFF, Some(fcc))
+    }
+
+    /// Open `LIST` with given list type inside current `RIFF` or `LIST`.
+    pub fn begin_list(&mut self, fcc: FourCC) -> io::Result<()> {
+        self.check_in_list()?;
+        self.open(ScopeKind::List, LIST, Some(fcc))
+    }
+
+    /// Open chunk. Payload is written through `Write` impl until `end` is called.
+    pub fn begin_chunk(&mut self, fcc: FourCC) -> io::Result<()> {
+        self.check_in_list()?;
+        self.open(ScopeKind::Chunk, fcc, None)
+    }
+
+    /// Write whole chunk at once.
+    pub fn write_chunk(&mut self, fcc: FourCC, data: &[u8]) -> io::Result<()> {
+        self.begin_chunk(fcc)?;
+        self.stream.write_all(data)?;
+        self.end()
+    }
+
+    /// Close innermost scope, patching its size and padding it to even length.
+    pub fn end(&mut self) -> io::Result<()> {
+        let scope = match self.scopes.pop() {
+            Some(scope) => scope,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "No open scope to end")),
+        };
+        let end = self.stream.seek(SeekFrom::Current(0))?;
+        let size = end - scope.start - 4;
+        if size > u32::max_value() as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?} is too big: {}", scope.kind, size)));
+        }
+        self.stream.seek(SeekFrom::Start(scope.start))?;
+        self.stream.write_u32::<LittleEndian>(size as u32)?;
+        self.stream.seek(SeekFrom::Start(end))?;
+        if size % 2 == 1 {
+            self.stream.write_all(&[0])?;
+        }
+        Ok(())
+    }
+
+    /// Number of open scopes.
+    pub fn depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// Close all open scopes and return underlying stream.
+    pub fn finish(mut self) -> io::Result<T> {
+        while !self.scopes.is_empty() {
+            self.end()?;
+        }
+        self.stream.flush()?;
+        Ok(self.stream)
+    }
+
+    fn open(&mut self, kind: ScopeKind, id: FourCC, fcc: Option<FourCC>) -> io::Result<()> {
+        self.stream.write_all(&id.0)?;
+        let start = self.stream.seek(SeekFrom::Current(0))?;
+        self.stream.write_u32::<LittleEndian>(0)?;
+        if let Some(fcc) = fcc {
+            self.stream.write_all(&fcc.0)?;
+        }
+        self.scopes.push(Scope {
+            kind: kind,
+            start: start,
+        });
+        Ok(())
+    }
+
+    fn check_in_list(&self) -> io::Result<()> {
+        match self.scopes.last() {
+            Some(&Scope { kind: ScopeKind::Chunk, .. }) => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "Chunk can't contain nested nodes"))
+            }
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Node must be inside RIFF form")),
+        }
+    }
+}
+
+impl<T> Write for RiffWriter<T>
+    where T: Write + Seek
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.scopes.last() {
+            Some(&Scope { kind: ScopeKind::Chunk, .. }) => self.stream.write(buf),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Raw data can be written only into chunk")),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<T> Debug for RiffWriter<T>
+    where T: Write + Seek
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "RiffWriter {{ scopes: {:?} }}", self.scopes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use fourcc::FourCC;
+    use riff::{Riff, Node};
+    use super::RiffWriter;
+
+    const TEST: FourCC = FourCC([b'T', b'E', b'S', b'T']);
+    const INFO: FourCC = FourCC([b'I', b'N', b'F', b'O']);
+    const ABCD: FourCC = FourCC([b'a', b'b', b'c', b'd']);
+
+    #[test]
+    fn sizes_and_padding() {
+        let mut writer = RiffWriter::new(Cursor::new(vec![]));
+        writer.begin_riff(TEST).unwrap();
+        writer.write_chunk(ABCD, b"xyz").unwrap();
+        writer.begin_list(INFO).unwrap();
+        writer.begin_chunk(ABCD).unwrap();
+        writer.write_all(b"12").unwrap();
+        writer.end().unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        assert_eq!(&data[..], &b"RIFF\x26\0\0\0TEST\
+                                 abcd\x03\0\0\0xyz\0\
+                                 LIST\x0e\0\0\0INFOabcd\x02\0\0\012"[..]);
+    }
+
+    #[test]
+    fn read_back() {
+        let mut writer = RiffWriter::new(Cursor::new(vec![]));
+        writer.begin_riff(TEST).unwrap();
+        writer.write_chunk(ABCD, b"odd").unwrap();
+        writer.write_chunk(INFO, b"even").unwrap();
+        let data = writer.finish().unwrap().into_inner();
+
+        let mut riff = Riff::new(Cursor::new(data)).unwrap();
+        let mut form = riff.iter().next().unwrap().unwrap();
+        assert_eq!(form.fourcc(), TEST);
+        let mut payloads = vec![];
+        for node in form.iter() {
+            match node.unwrap() {
+                Node::Chunk(mut chunk) => {
+                    let mut data = vec![];
+                    chunk.read().read_to_end(&mut data).unwrap();
+                    payloads.push((chunk.fourcc(), data));
+                }
+                Node::List(_) => panic!("unexpected list"),
+            }
+        }
+        assert_eq!(payloads, vec![(ABCD, b"odd".to_vec()), (INFO, b"even".to_vec())]);
+    }
+
+    #[test]
+    fn misuse() {
+        let mut writer = RiffWriter::new(Cursor::new(vec![]));
+        assert!(writer.begin_list(INFO).is_err());
+        assert!(writer.write_all(b"x").is_err());
+        writer.begin_riff(TEST).unwrap();
+        assert!(writer.begin_riff(TEST).is_err());
+        writer.begin_chunk(ABCD).unwrap();
+        assert!(writer.begin_chunk(ABCD).is_err());
+        writer.end().unwrap();
+        writer.end().unwrap();
+        assert!(writer.end().is_err());
+    }
+}
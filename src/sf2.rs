@@ -0,0 +1,624 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::fmt;
+
+use byteorder::{ReadBytesExt, LittleEndian};
+
+use riff;
+use deser::Deser;
+use fourcc::FourCC;
+
+pub const FCC_SFBK: FourCC = FourCC([b's', b'f', b'b', b'k']);
+pub const FCC_INFO: FourCC = FourCC([b'I', b'N', b'F', b'O']);
+pub const FCC_SDTA: FourCC = FourCC([b's', b'd', b't', b'a']);
+pub const FCC_PDTA: FourCC = FourCC([b'p', b'd', b't', b'a']);
+pub const FCC_IFIL: FourCC = FourCC([b'i', b'f', b'i', b'l']);
+pub const FCC_IVER: FourCC = FourCC([b'i', b'v', b'e', b'r']);
+pub const FCC_INAM: FourCC = FourCC([b'I', b'N', b'A', b'M']);
+pub const FCC_SMPL: FourCC = FourCC([b's', b'm', b'p', b'l']);
+pub const FCC_SM24: FourCC = FourCC([b's', b'm', b'2', b'4']);
+pub const FCC_PHDR: FourCC = FourCC([b'p', b'h', b'd', b'r']);
+pub const FCC_PBAG: FourCC = FourCC([b'p', b'b', b'a', b'g']);
+pub const FCC_PMOD: FourCC = FourCC([b'p', b'm', b'o', b'd']);
+pub const FCC_PGEN: FourCC = FourCC([b'p', b'g', b'e', b'n']);
+pub const FCC_INST: FourCC = FourCC([b'i', b'n', b's', b't']);
+pub const FCC_IBAG: FourCC = FourCC([b'i', b'b', b'a', b'g']);
+pub const FCC_IMOD: FourCC = FourCC([b'i', b'm', b'o', b'd']);
+pub const FCC_IGEN: FourCC = FourCC([b'i', b'g', b'e', b'n']);
+pub const FCC_SHDR: FourCC = FourCC([b's', b'h', b'd', b'r']);
+
+pub const GEN_KEY_RANGE: u16 = 43;
+pub const GEN_VEL_RANGE: u16 = 44;
+/// Last generator of preset zone, amount is instrument index.
+pub const GEN_INSTRUMENT: u16 = 41;
+/// Last generator of instrument zone, amount is sample index.
+pub const GEN_SAMPLE_ID: u16 = 53;
+
+/// `sample_type` bit of samples stored in sound ROM instead of `smpl`.
+pub const SAMPLE_TYPE_ROM: u16 = 0x8000;
+
+fn format_error(message: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Text up to first NUL of fixed size name field.
+fn name(bytes: &[u8]) -> String {
+	let end = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+	String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/*
+
+    pdta records
+
+*/
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct PresetHeader {
+	pub name: [u8; 20],
+	pub preset: u16,
+	pub bank: u16,
+	pub bag_index: u16,
+	pub library: u32,
+	pub genre: u32,
+	pub morphology: u32,
+}
+
+impl PresetHeader {
+	pub fn name(&self) -> String {
+		name(&self.name)
+	}
+}
+
+/// Record of `pbag` and `ibag`.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct Bag {
+	pub gen_index: u16,
+	pub mod_index: u16,
+}
+
+/// Record of `pmod` and `imod`.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct Modulator {
+	pub src_oper: u16,
+	pub dest_oper: u16,
+	pub amount: i16,
+	pub amount_src_oper: u16,
+	pub trans_oper: u16,
+}
+
+/// Record of `pgen` and `igen`.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct Generator {
+	pub oper: u16,
+	pub amount: [u8; 2],
+}
+
+impl Generator {
+	/// Low and high bytes, for key and velocity ranges.
+	pub fn range(&self) -> (u8, u8) {
+		(self.amount[0], self.amount[1])
+	}
+	pub fn amount_i16(&self) -> i16 {
+		self.amount_u16() as i16
+	}
+	pub fn amount_u16(&self) -> u16 {
+		self.amount[0] as u16 | (self.amount[1] as u16) << 8
+	}
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct InstrumentHeader {
+	pub name: [u8; 20],
+	pub bag_index: u16,
+}
+
+impl InstrumentHeader {
+	pub fn name(&self) -> String {
+		name(&self.name)
+	}
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Deser, Ser)]
+pub struct SampleHeader {
+	pub name: [u8; 20],
+	/// Sample frame index in `smpl`.
+	pub start: u32,
+	pub end: u32,
+	pub start_loop: u32,
+	pub end_loop: u32,
+	pub sample_rate: u32,
+	pub original_pitch: u8,
+	pub pitch_correction: i8,
+	pub sample_link: u16,
+	pub sample_type: u16,
+}
+
+impl SampleHeader {
+	pub fn name(&self) -> String {
+		name(&self.name)
+	}
+	/// Sample data lives in sound ROM, `start`/`end` don't index `smpl`.
+	pub fn is_rom(&self) -> bool {
+		self.sample_type & SAMPLE_TYPE_ROM != 0
+	}
+}
+
+//
+
+/// Generators and modulators of one bag.
+#[derive(Clone, Debug)]
+pub struct Zone {
+	pub generators: Vec<Generator>,
+	pub modulators: Vec<Modulator>,
+}
+
+impl Zone {
+	pub fn generator(&self, oper: u16) -> Option<&Generator> {
+		self.generators.iter().find(|gen| gen.oper == oper)
+	}
+
+	/// Instrument index of preset zone. Zone without it is global.
+	pub fn instrument(&self) -> Option<u16> {
+		self.generators.last().and_then(|gen| if gen.oper == GEN_INSTRUMENT { Some(gen.amount_u16()) } else { None })
+	}
+
+	/// Sample index of instrument zone. Zone without it is global.
+	pub fn sample(&self) -> Option<u16> {
+		self.generators.last().and_then(|gen| if gen.oper == GEN_SAMPLE_ID { Some(gen.amount_u16()) } else { None })
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct Preset {
+	pub header: PresetHeader,
+	pub zones: Vec<Zone>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Instrument {
+	pub header: InstrumentHeader,
+	pub zones: Vec<Zone>,
+}
+
+/// `INFO` list of SoundFont.
+#[derive(Clone, Debug, Default)]
+pub struct Info {
+	/// Major and minor version from `ifil`.
+	pub version: (u16, u16),
+	/// Version of sound ROM from `iver`.
+	pub rom_version: Option<(u16, u16)>,
+	/// Text entries such as `INAM`, `isng`, `ICOP`.
+	pub entries: Vec<(FourCC, String)>,
+}
+
+impl Info {
+	pub fn get(&self, fcc: FourCC) -> Option<&str> {
+		self.entries.iter().find(|entry| entry.0 == fcc).map(|entry| &entry.1[..])
+	}
+
+	pub fn name(&self) -> Option<&str> {
+		self.get(FCC_INAM)
+	}
+
+	fn from_list<'a, T: 'a + Read + Seek + fmt::Debug>(list: &mut riff::List<'a, T>) -> io::Result<Self> {
+		let mut info = Info::default();
+		let mut has_version = false;
+		for item in list.iter() {
+			let mut chunk = match item? {
+				riff::Node::Chunk(chunk) => chunk,
+				riff::Node::List(_) => continue,
+			};
+			let fcc = chunk.fourcc();
+			let mut read = chunk.read();
+			match fcc {
+				FCC_IFIL => {
+					info.version = (read.read_u16::<LittleEndian>()?, read.read_u16::<LittleEndian>()?);
+					has_version = true;
+				}
+				FCC_IVER => {
+					info.rom_version = Some((read.read_u16::<LittleEndian>()?, read.read_u16::<LittleEndian>()?));
+				}
+				_ => {
+					let mut bytes = vec![];
+					read.read_to_end(&mut bytes)?;
+					info.entries.push((fcc, name(&bytes)));
+				}
+			}
+		}
+		if !has_version {
+			return Err(format_error("INFO without ifil".to_string()));
+		}
+		Ok(info)
+	}
+}
+
+/// Raw `pdta` records, terminal records included.
+#[derive(Clone, Debug, Default)]
+struct Pdta {
+	phdr: Vec<PresetHeader>,
+	pbag: Vec<Bag>,
+	pmod: Vec<Modulator>,
+	pgen: Vec<Generator>,
+	inst: Vec<InstrumentHeader>,
+	ibag: Vec<Bag>,
+	imod: Vec<Modulator>,
+	igen: Vec<Generator>,
+	shdr: Vec<SampleHeader>,
+}
+
+/// Splits `bags[from..to]` into zones.
+fn zones(bags: &[Bag], gens: &[Generator], mods: &[Modulator], from: usize, to: usize) -> io::Result<Vec<Zone>> {
+	if from > to || to >= bags.len() {
+		return Err(format_error(format!("Bag range {}..{} out of {} bags", from, to, bags.len())));
+	}
+	(from..to).map(|index| {
+		let (gen_from, gen_to) = (bags[index].gen_index as usize, bags[index + 1].gen_index as usize);
+		let (mod_from, mod_to) = (bags[index].mod_index as usize, bags[index + 1].mod_index as usize);
+		if gen_from > gen_to || gen_to > gens.len() || mod_from > mod_to || mod_to > mods.len() {
+			return Err(format_error(format!("Bag {} points outside of generators or modulators", index)));
+		}
+		Ok(Zone {
+			generators: gens[gen_from..gen_to].to_vec(),
+			modulators: mods[mod_from..mod_to].to_vec(),
+		})
+	}).collect()
+}
+
+/// Parsed `RIFF sfbk` file.
+#[derive(Debug)]
+pub struct SoundFont<'a, T: 'a + Read + Seek + fmt::Debug> {
+	info: Info,
+	presets: Vec<Preset>,
+	instruments: Vec<Instrument>,
+	samples: Vec<SampleHeader>,
+	smpl: Option<riff::Chunk<'a, T>>,
+	sm24: Option<riff::Chunk<'a, T>>,
+}
+
+impl<'a, T: 'a + Read + Seek + fmt::Debug> SoundFont<'a, T> {
+	pub fn from_riff(riff: &'a mut riff::Riff<T>) -> io::Result<Self> {
+		let mut form = riff.iter().next().ok_or_else(|| format_error("No RIFF form".to_string()))??;
+		if form.fourcc() != FCC_SFBK {
+			return Err(format_error(format!("Expected sfbk form, found {}", form.fourcc())));
+		}
+		let mut info = None;
+		let mut smpl = None;
+		let mut sm24 = None;
+		let mut pdta = None;
+		for item in form.iter() {
+			let mut list = match item? {
+				riff::Node::List(list) => list,
+				riff::Node::Chunk(chunk) => return Err(format_error(format!("Unexpected chunk {} in sfbk", chunk.fourcc()))),
+			};
+			match list.fourcc() {
+				FCC_INFO => info = Some(Info::from_list(&mut list)?),
+				FCC_SDTA => {
+					for item in list.iter() {
+						if let riff::Node::Chunk(chunk) = item? {
+							match chunk.fourcc() {
+								FCC_SMPL => smpl = Some(chunk),
+								FCC_SM24 => sm24 = Some(chunk),
+								_ => {}
+							}
+						}
+					}
+				}
+				FCC_PDTA => pdta = Some(read_pdta(&mut list)?),
+				_ => {}
+			}
+		}
+		let info = info.ok_or_else(|| format_error("No INFO list".to_string()))?;
+		let pdta = pdta.ok_or_else(|| format_error("No pdta list".to_string()))?;
+		if pdta.phdr.is_empty() || pdta.inst.is_empty() || pdta.shdr.is_empty() {
+			return Err(format_error("pdta lacks terminal records".to_string()));
+		}
+
+		let mut presets = vec![];
+		for pair in pdta.phdr.windows(2) {
+			presets.push(Preset {
+				header: pair[0],
+				zones: zones(&pdta.pbag, &pdta.pgen, &pdta.pmod, pair[0].bag_index as usize, pair[1].bag_index as usize)?,
+			});
+		}
+		let mut instruments = vec![];
+		for pair in pdta.inst.windows(2) {
+			instruments.push(Instrument {
+				header: pair[0],
+				zones: zones(&pdta.ibag, &pdta.igen, &pdta.imod, pair[0].bag_index as usize, pair[1].bag_index as usize)?,
+			});
+		}
+		let mut samples = pdta.shdr;
+		samples.pop();
+
+		for preset in &presets {
+			for zone in &preset.zones {
+				if zone.instrument().map_or(false, |index| index as usize >= instruments.len()) {
+					return Err(format_error(format!("Preset {:?} refers to missing instrument", preset.header.name())));
+				}
+			}
+		}
+		for instrument in &instruments {
+			for zone in &instrument.zones {
+				if zone.sample().map_or(false, |index| index as usize >= samples.len()) {
+					return Err(format_error(format!("Instrument {:?} refers to missing sample", instrument.header.name())));
+				}
+			}
+		}
+		if let Some(ref smpl) = smpl {
+			let frames = smpl.size() / 2;
+			for sample in samples.iter().filter(|sample| !sample.is_rom()) {
+				if sample.start > sample.end || sample.end as u64 > frames {
+					return Err(format_error(format!("Sample {:?} is outside of smpl", sample.name())));
+				}
+			}
+		}
+		// `sm24` is ignored before 2.04 and when its size doesn't match `smpl`, as the spec says.
+		let frames = smpl.as_ref().map(|smpl| smpl.size() / 2);
+		let sm24 = sm24.filter(|sm24| {
+			info.version >= (2, 4) && frames.map_or(false, |frames| sm24.size() == frames || sm24.size() == frames + frames % 2)
+		});
+
+		Ok(SoundFont {
+			info: info,
+			presets: presets,
+			instruments: instruments,
+			samples: samples,
+			smpl: smpl,
+			sm24: sm24,
+		})
+	}
+
+	pub fn info(&self) -> &Info {
+		&self.info
+	}
+
+	pub fn presets(&self) -> &[Preset] {
+		&self.presets
+	}
+
+	pub fn instruments(&self) -> &[Instrument] {
+		&self.instruments
+	}
+
+	pub fn samples(&self) -> &[SampleHeader] {
+		&self.samples
+	}
+
+	fn sample_header(&self, index: usize) -> io::Result<SampleHeader> {
+		self.samples.get(index).cloned().ok_or_else(|| {
+			io::Error::new(io::ErrorKind::InvalidInput, format!("No sample {} of {}", index, self.samples.len()))
+		})
+	}
+
+	/// 16-bit data of sample `index`. Fails for ROM samples.
+	pub fn sample_data(&mut self, index: usize) -> io::Result<Vec<i16>> {
+		let sample = self.sample_header(index)?;
+		if sample.is_rom() {
+			return Err(format_error(format!("Sample {:?} is stored in sound ROM", sample.name())));
+		}
+		let (start, end) = (sample.start as u64, sample.end as u64);
+		let smpl = self.smpl.as_mut().ok_or_else(|| format_error("No smpl chunk".to_string()))?;
+		let mut read = smpl.read();
+		read.seek(SeekFrom::Start(start * 2))?;
+		let mut data = vec![0; (end - start) as usize];
+		read.read_i16_into::<LittleEndian>(&mut data)?;
+		Ok(data)
+	}
+
+	/// 24-bit data of sample `index`, combining `smpl` with low bytes from `sm24`.
+	/// `None` if there is no usable `sm24`.
+	pub fn sample_data_24(&mut self, index: usize) -> io::Result<Option<Vec<i32>>> {
+		let sample = self.sample_header(index)?;
+		let (start, end) = (sample.start as u64, sample.end as u64);
+		let high = self.sample_data(index)?;
+		let sm24 = match self.sm24.as_mut() {
+			Some(sm24) => sm24,
+			None => return Ok(None),
+		};
+		let mut read = sm24.read();
+		read.seek(SeekFrom::Start(start))?;
+		let mut low = vec![0; (end - start) as usize];
+		read.read_exact(&mut low)?;
+		Ok(Some(high.iter().zip(low).map(|(&high, low)| (high as i32) << 8 | low as i32).collect()))
+	}
+}
+
+fn read_pdta<'a, T: 'a + Read + Seek + fmt::Debug>(list: &mut riff::List<'a, T>) -> io::Result<Pdta> {
+	let mut pdta = Pdta::default();
+	for item in list.iter() {
+		let mut chunk = match item? {
+			riff::Node::Chunk(chunk) => chunk,
+			riff::Node::List(_) => continue,
+		};
+		let fcc = chunk.fourcc();
+		let mut read = chunk.read();
+		match fcc {
+			FCC_PHDR => pdta.phdr = Deser::deser(&mut read)?,
+			FCC_PBAG => pdta.pbag = Deser::deser(&mut read)?,
+			FCC_PMOD => pdta.pmod = Deser::deser(&mut read)?,
+			FCC_PGEN => pdta.pgen = Deser::deser(&mut read)?,
+			FCC_INST => pdta.inst = Deser::deser(&mut read)?,
+			FCC_IBAG => pdta.ibag = Deser::deser(&mut read)?,
+			FCC_IMOD => pdta.imod = Deser::deser(&mut read)?,
+			FCC_IGEN => pdta.igen = Deser::deser(&mut read)?,
+			FCC_SHDR => pdta.shdr = Deser::deser(&mut read)?,
+			_ => {}
+		}
+	}
+	Ok(pdta)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use deser::Ser;
+	use fourcc::FourCC;
+	use riff::{Riff, RiffWriter};
+	use super::*;
+
+	fn name20(text: &str) -> [u8; 20] {
+		let mut name = [0; 20];
+		name[..text.len()].copy_from_slice(text.as_bytes());
+		name
+	}
+
+	fn ser<S: Ser>(records: &[S]) -> Vec<u8> {
+		let mut data = vec![];
+		for record in records {
+			record.ser(&mut data).unwrap();
+		}
+		data
+	}
+
+	fn sample(name: &str, start: u32, end: u32, sample_type: u16) -> SampleHeader {
+		SampleHeader {
+			name: name20(name),
+			start: start,
+			end: end,
+			start_loop: start,
+			end_loop: end,
+			sample_rate: 22050,
+			original_pitch: 60,
+			pitch_correction: 0,
+			sample_link: 0,
+			sample_type: sample_type,
+		}
+	}
+
+	/// One preset, one instrument playing sample 0, one RAM and one ROM sample.
+	fn file() -> Vec<u8> {
+		build(1, None, &[sample("Ram", 2, 6, 1), sample("Rom", 0, 100000, SAMPLE_TYPE_ROM | 1)])
+	}
+
+	/// Version 2.`minor` file with 8 frames in `smpl`, optional `sm24` and `samples`.
+	fn build(minor: u8, sm24: Option<&[u8]>, samples: &[SampleHeader]) -> Vec<u8> {
+		let mut writer = RiffWriter::new(Cursor::new(vec![]));
+		writer.begin_riff(FCC_SFBK).unwrap();
+		writer.begin_list(FCC_INFO).unwrap();
+		writer.write_chunk(FCC_IFIL, &[2, 0, minor, 0]).unwrap();
+		writer.write_chunk(FCC_INAM, b"Test\0").unwrap();
+		writer.end().unwrap();
+		writer.begin_list(FCC_SDTA).unwrap();
+		let smpl = (0..8i16).flat_map(|i| vec![(i * 100) as u8, ((i * 100) >> 8) as u8]).collect::<Vec<u8>>();
+		writer.write_chunk(FCC_SMPL, &smpl).unwrap();
+		if let Some(sm24) = sm24 {
+			writer.write_chunk(FCC_SM24, sm24).unwrap();
+		}
+		writer.end().unwrap();
+
+		let preset = |name: &str, bag| PresetHeader { name: name20(name), preset: 0, bank: 0, bag_index: bag, library: 0, genre: 0, morphology: 0 };
+		let bag = |gen| Bag { gen_index: gen, mod_index: 0 };
+		let gen = |oper, amount: u16| Generator { oper: oper, amount: [amount as u8, (amount >> 8) as u8] };
+		let modulator = Modulator { src_oper: 0, dest_oper: 0, amount: 0, amount_src_oper: 0, trans_oper: 0 };
+		let inst = |name: &str, bag| InstrumentHeader { name: name20(name), bag_index: bag };
+		writer.begin_list(FCC_PDTA).unwrap();
+		writer.write_chunk(FCC_PHDR, &ser(&[preset("Piano", 0), preset("EOP", 1)])).unwrap();
+		writer.write_chunk(FCC_PBAG, &ser(&[bag(0), bag(2)])).unwrap();
+		writer.write_chunk(FCC_PMOD, &ser(&[modulator])).unwrap();
+		writer.write_chunk(FCC_PGEN, &ser(&[gen(GEN_KEY_RANGE, 0x7f00), gen(GEN_INSTRUMENT, 0), gen(0, 0)])).unwrap();
+		writer.write_chunk(FCC_INST, &ser(&[inst("Inst", 0), inst("EOI", 1)])).unwrap();
+		writer.write_chunk(FCC_IBAG, &ser(&[bag(0), bag(1)])).unwrap();
+		writer.write_chunk(FCC_IMOD, &ser(&[modulator])).unwrap();
+		writer.write_chunk(FCC_IGEN, &ser(&[gen(GEN_SAMPLE_ID, 0), gen(0, 0)])).unwrap();
+		let mut shdr = samples.to_vec();
+		shdr.push(sample("EOS", 0, 0, 0));
+		writer.write_chunk(FCC_SHDR, &ser(&shdr)).unwrap();
+		writer.end().unwrap();
+		writer.end().unwrap();
+		writer.finish().unwrap().into_inner()
+	}
+
+	#[test]
+	fn presets_instruments_samples() {
+		let mut riff = Riff::new(Cursor::new(file())).unwrap();
+		let mut sf2 = SoundFont::from_riff(&mut riff).unwrap();
+		assert_eq!(sf2.info().version, (2, 1));
+		assert_eq!(sf2.info().name(), Some("Test"));
+		assert_eq!(sf2.presets().len(), 1);
+		assert_eq!(sf2.presets()[0].header.name(), "Piano");
+		let zone = &sf2.presets()[0].zones[0];
+		assert_eq!(zone.instrument(), Some(0));
+		assert_eq!(zone.generator(GEN_KEY_RANGE).unwrap().range(), (0, 0x7f));
+		assert_eq!(sf2.instruments()[0].zones[0].sample(), Some(0));
+		assert_eq!(sf2.samples().len(), 2);
+		assert_eq!(sf2.sample_data(0).unwrap(), vec![200, 300, 400, 500]);
+		assert_eq!(sf2.sample_data_24(0).unwrap(), None);
+	}
+
+	#[test]
+	fn rom_samples() {
+		let mut riff = Riff::new(Cursor::new(file())).unwrap();
+		let mut sf2 = SoundFont::from_riff(&mut riff).unwrap();
+		assert!(sf2.samples()[1].is_rom());
+		assert!(!sf2.samples()[0].is_rom());
+		let err = sf2.sample_data(1).unwrap_err();
+		assert!(err.to_string().contains("ROM"), "{}", err);
+		assert!(sf2.sample_data_24(1).is_err());
+	}
+
+	#[test]
+	fn sample_outside_smpl() {
+		let data = build(1, None, &[sample("Ram", 2, 6, 1), sample("Big", 0, 100000, 1)]);
+		let mut riff = Riff::new(Cursor::new(data)).unwrap();
+		assert!(SoundFont::from_riff(&mut riff).is_err());
+	}
+
+	#[test]
+	fn bad_sample_index() {
+		let mut riff = Riff::new(Cursor::new(file())).unwrap();
+		let mut sf2 = SoundFont::from_riff(&mut riff).unwrap();
+		assert_eq!(sf2.sample_data(2).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+		assert_eq!(sf2.sample_data_24(100).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+	}
+
+	#[test]
+	fn sm24_low_bytes() {
+		let sm24 = [0, 1, 2, 3, 0x80, 5, 6, 7];
+		let mut riff = Riff::new(Cursor::new(build(4, Some(&sm24), &[sample("Ram", 2, 6, 1)]))).unwrap();
+		let mut sf2 = SoundFont::from_riff(&mut riff).unwrap();
+		assert_eq!(sf2.sample_data_24(0).unwrap(), Some(vec![200 << 8 | 2, 300 << 8 | 3, 400 << 8 | 0x80, 500 << 8 | 5]));
+	}
+
+	#[test]
+	fn sm24_ignored() {
+		let sm24 = [0; 8];
+		// Version 2.01 predates `sm24`.
+		let mut riff = Riff::new(Cursor::new(build(1, Some(&sm24), &[sample("Ram", 2, 6, 1)]))).unwrap();
+		assert_eq!(SoundFont::from_riff(&mut riff).unwrap().sample_data_24(0).unwrap(), None);
+		// Size doesn't match 8 frames of `smpl`.
+		let mut riff = Riff::new(Cursor::new(build(4, Some(&sm24[..6]), &[sample("Ram", 2, 6, 1)]))).unwrap();
+		assert_eq!(SoundFont::from_riff(&mut riff).unwrap().sample_data_24(0).unwrap(), None);
+	}
+
+	#[test]
+	fn unknown_form() {
+		let mut writer = RiffWriter::new(Cursor::new(vec![]));
+		writer.begin_riff(FourCC::from_str("WAVE").unwrap()).unwrap();
+		writer.end().unwrap();
+		let mut riff = Riff::new(Cursor::new(writer.finish().unwrap().into_inner())).unwrap();
+		assert!(SoundFont::from_riff(&mut riff).is_err());
+	}
+
+	#[test]
+	fn sample_header_fields() {
+		let data = b"Piano C4\0\0\0\0\0\0\0\0\0\0\0\0\
+		             \x10\0\0\0\x00\x01\x02\0\x20\0\0\0\x30\0\0\0\x44\xac\0\0\
+		             \x3c\xfb\x05\0\x01\x80";
+		let header = SampleHeader::deser(&mut &data[..]).unwrap();
+		assert_eq!(header.name(), "Piano C4");
+		assert_eq!(({ header.start }, { header.end }), (0x10, 0x20100));
+		assert_eq!(({ header.start_loop }, { header.end_loop }, { header.sample_rate }), (0x20, 0x30, 44100));
+		assert_eq!(({ header.original_pitch }, { header.pitch_correction }), (60, -5));
+		assert_eq!(({ header.sample_link }, { header.sample_type }), (5, 0x8001));
+		assert!(header.is_rom());
+		assert_eq!(ser(&[header]), &data[..]);
+	}
+}
+
@@ -0,0 +1,442 @@
+use std::io::{self, Read, Write, Seek};
+use std::fmt;
+
+use byteorder::{WriteBytesExt, LittleEndian};
+
+use riff;
+use deser::{Deser, Ser};
+use fourcc::FourCC;
+
+pub const FCC_BEXT: FourCC = FourCC([b'b', b'e', b'x', b't']);
+pub const FCC_IXML: FourCC = FourCC([b'i', b'X', b'M', b'L']);
+pub const FCC_CUE:  FourCC = FourCC([b'c', b'u', b'e', b' ']);
+pub const FCC_ADTL: FourCC = FourCC([b'a', b'd', b't', b'l']);
+pub const FCC_LABL: FourCC = FourCC([b'l', b'a', b'b', b'l']);
+pub const FCC_NOTE: FourCC = FourCC([b'n', b'o', b't', b'e']);
+pub const FCC_LTXT: FourCC = FourCC([b'l', b't', b'x', b't']);
+pub const FCC_SMPL: FourCC = FourCC([b's', b'm', b'p', b'l']);
+
+fn read_fourcc<R: Read>(read: &mut R) -> io::Result<FourCC> {
+	let mut fcc = [0; 4];
+	read.read_exact(&mut fcc)?;
+	Ok(FourCC(fcc))
+}
+
+/// Text up to first NUL.
+fn from_c_string(bytes: &[u8]) -> String {
+	let end = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+	String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn read_rest<R: Read>(read: &mut R) -> io::Result<Vec<u8>> {
+	let mut bytes = vec![];
+	read.read_to_end(&mut bytes)?;
+	Ok(bytes)
+}
+
+/// Broadcast WAV `bext` chunk (EBU Tech 3285).
+#[derive(Clone, Debug, PartialEq, Deser, Ser)]
+pub struct Bext {
+	#[deser(fixed = 256)]
+	pub description: String,
+	#[deser(fixed = 32)]
+	pub originator: String,
+	#[deser(fixed = 32)]
+	pub originator_reference: String,
+	/// `yyyy-mm-dd`
+	#[deser(fixed = 10)]
+	pub origination_date: String,
+	/// `hh:mm:ss`
+	#[deser(fixed = 8)]
+	pub origination_time: String,
+	/// Sample count since midnight of first sample.
+	pub time_reference: u64,
+	pub version: u16,
+	pub umid: [u8; 64],
+	pub loudness_value: i16,
+	pub loudness_range: i16,
+	pub max_true_peak_level: i16,
+	pub max_momentary_loudness: i16,
+	pub max_short_term_loudness: i16,
+	/// Zero in current version of the specification, kept as found.
+	pub reserved: [u8; 180],
+	#[deser(rest)]
+	pub coding_history: String,
+}
+
+impl Default for Bext {
+	fn default() -> Self {
+		Bext {
+			description: String::new(),
+			originator: String::new(),
+			originator_reference: String::new(),
+			origination_date: String::new(),
+			origination_time: String::new(),
+			time_reference: 0,
+			version: 0,
+			umid: [0; 64],
+			loudness_value: 0,
+			loudness_range: 0,
+			max_true_peak_level: 0,
+			max_momentary_loudness: 0,
+			max_short_term_loudness: 0,
+			reserved: [0; 180],
+			coding_history: String::new(),
+		}
+	}
+}
+
+/// `iXML` chunk holding production metadata as XML document.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deser, Ser)]
+pub struct IXml {
+	#[deser(rest)]
+	pub xml: String,
+}
+
+/// Marker in `cue ` chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deser, Ser)]
+pub struct CuePoint {
+	pub id: u32,
+	/// Sample position in play order.
+	pub position: u32,
+	/// Chunk holding the sample, `data` for plain files.
+	pub chunk: FourCC,
+	pub chunk_start: u32,
+	pub block_start: u32,
+	/// Sample frame offset inside the block.
+	pub sample_offset: u32,
+}
+
+/// `cue ` chunk.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deser, Ser)]
+pub struct Cue {
+	#[deser(prefix = "u32")]
+	pub points: Vec<CuePoint>,
+}
+
+/// `ltxt` entry: text attached to a region starting at cue point.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabeledText {
+	pub cue_id: u32,
+	pub sample_length: u32,
+	pub purpose: FourCC,
+	pub country: u16,
+	pub language: u16,
+	pub dialect: u16,
+	pub code_page: u16,
+	pub text: String,
+}
+
+/// Entry of `LIST adtl`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdtlEntry {
+	/// `labl`
+	Label { cue_id: u32, text: String },
+	/// `note`
+	Note { cue_id: u32, text: String },
+	/// `ltxt`
+	LabeledText(LabeledText),
+	/// Any other chunk, kept as is.
+	Other(FourCC, Vec<u8>),
+}
+
+impl AdtlEntry {
+	pub fn fourcc(&self) -> FourCC {
+		match *self {
+			AdtlEntry::Label { .. } => FCC_LABL,
+			AdtlEntry::Note { .. } => FCC_NOTE,
+			AdtlEntry::LabeledText(_) => FCC_LTXT,
+			AdtlEntry::Other(fcc, _) => fcc,
+		}
+	}
+
+	pub fn cue_id(&self) -> Option<u32> {
+		match *self {
+			AdtlEntry::Label { cue_id, .. } | AdtlEntry::Note { cue_id, .. } => Some(cue_id),
+			AdtlEntry::LabeledText(ref ltxt) => Some(ltxt.cue_id),
+			AdtlEntry::Other(..) => None,
+		}
+	}
+
+	/// Reads entry from payload of chunk `fcc` with integers in byte order `endian`.
+	pub fn read<R: Read>(fcc: FourCC, endian: riff::Endian, read: &mut R) -> io::Result<Self> {
+		Ok(match fcc {
+			FCC_LABL => AdtlEntry::Label {
+				cue_id: endian.read_u32(read)?,
+				text: from_c_string(&read_rest(read)?),
+			},
+			FCC_NOTE => AdtlEntry::Note {
+				cue_id: endian.read_u32(read)?,
+				text: from_c_string(&read_rest(read)?),
+			},
+			FCC_LTXT => AdtlEntry::LabeledText(LabeledText {
+				cue_id: endian.read_u32(read)?,
+				sample_length: endian.read_u32(read)?,
+				purpose: read_fourcc(read)?,
+				country: endian.read_u16(read)?,
+				language: endian.read_u16(read)?,
+				dialect: endian.read_u16(read)?,
+				code_page: endian.read_u16(read)?,
+				text: from_c_string(&read_rest(read)?),
+			}),
+			fcc => AdtlEntry::Other(fcc, read_rest(read)?),
+		})
+	}
+
+	/// Writes entry payload, without chunk header.
+	pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+		match *self {
+			AdtlEntry::Label { cue_id, ref text } | AdtlEntry::Note { cue_id, ref text } => {
+				write.write_u32::<LittleEndian>(cue_id)?;
+				write.write_all(text.as_bytes())?;
+				write.write_all(&[0])
+			}
+			AdtlEntry::LabeledText(ref ltxt) => {
+				write.write_u32::<LittleEndian>(ltxt.cue_id)?;
+				write.write_u32::<LittleEndian>(ltxt.sample_length)?;
+				write.write_all(&ltxt.purpose.0)?;
+				write.write_u16::<LittleEndian>(ltxt.country)?;
+				write.write_u16::<LittleEndian>(ltxt.language)?;
+				write.write_u16::<LittleEndian>(ltxt.dialect)?;
+				write.write_u16::<LittleEndian>(ltxt.code_page)?;
+				if !ltxt.text.is_empty() {
+					write.write_all(ltxt.text.as_bytes())?;
+					write.write_all(&[0])?;
+				}
+				Ok(())
+			}
+			AdtlEntry::Other(_, ref data) => write.write_all(data),
+		}
+	}
+}
+
+/// `LIST adtl`: labels and notes for cue points.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Adtl {
+	pub entries: Vec<AdtlEntry>,
+}
+
+impl Adtl {
+	pub fn from_list<'a, T>(list: &mut riff::List<'a, T>) -> io::Result<Self>
+		where T: 'a + Read + Seek + fmt::Debug
+	{
+		if list.fourcc() != FCC_ADTL {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Expected adtl list, found {}", list.fourcc())));
+		}
+		let mut entries = vec![];
+		for item in list.iter() {
+			match item? {
+				riff::Node::Chunk(mut chunk) => entries.push(AdtlEntry::read(chunk.fourcc(), chunk.endian(), &mut chunk.read())?),
+				riff::Node::List(_) => continue,
+			}
+		}
+		Ok(Adtl { entries: entries })
+	}
+
+	/// Writes whole `LIST adtl`.
+	pub fn write<W: Write + Seek>(&self, writer: &mut riff::RiffWriter<W>) -> io::Result<()> {
+		writer.begin_list(FCC_ADTL)?;
+		for entry in &self.entries {
+			writer.begin_chunk(entry.fourcc())?;
+			entry.write(writer)?;
+			writer.end()?;
+		}
+		writer.end()
+	}
+
+	pub fn label(&self, cue_id: u32) -> Option<&str> {
+		self.entries.iter().filter_map(|entry| match *entry {
+			AdtlEntry::Label { cue_id: id, ref text } if id == cue_id => Some(&text[..]),
+			_ => None,
+		}).next()
+	}
+
+	pub fn note(&self, cue_id: u32) -> Option<&str> {
+		self.entries.iter().filter_map(|entry| match *entry {
+			AdtlEntry::Note { cue_id: id, ref text } if id == cue_id => Some(&text[..]),
+			_ => None,
+		}).next()
+	}
+}
+
+/// Loop in `smpl` chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deser, Ser)]
+pub struct SampleLoop {
+	pub cue_id: u32,
+	/// 0 - forward, 1 - alternating, 2 - backward.
+	pub kind: u32,
+	pub start: u32,
+	/// Last sample of the loop, inclusive.
+	pub end: u32,
+	pub fraction: u32,
+	/// 0 means infinite.
+	pub play_count: u32,
+}
+
+/// `smpl` chunk: sampler parameters and loop points.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Smpl {
+	pub manufacturer: u32,
+	pub product: u32,
+	/// Nanoseconds per sample.
+	pub sample_period: u32,
+	pub midi_unity_note: u32,
+	pub midi_pitch_fraction: u32,
+	pub smpte_format: u32,
+	pub smpte_offset: u32,
+	pub loops: Vec<SampleLoop>,
+	pub sampler_data: Vec<u8>,
+}
+
+// Both counts precede both arrays, so the header is read by hand.
+impl Deser for Smpl {
+	fn deser<R: Read + fmt::Debug>(read: &mut R) -> io::Result<Self> {
+		Self::deser_endian(read, riff::Endian::Little)
+	}
+
+	fn deser_endian<R: Read + fmt::Debug>(read: &mut R, endian: riff::Endian) -> io::Result<Self> {
+		let manufacturer = endian.read_u32(read)?;
+		let product = endian.read_u32(read)?;
+		let sample_period = endian.read_u32(read)?;
+		let midi_unity_note = endian.read_u32(read)?;
+		let midi_pitch_fraction = endian.read_u32(read)?;
+		let smpte_format = endian.read_u32(read)?;
+		let smpte_offset = endian.read_u32(read)?;
+		let count = endian.read_u32(read)?;
+		let data_size = endian.read_u32(read)?;
+		let mut loops = vec![];
+		for _ in 0..count {
+			loops.push(SampleLoop::deser_endian(read, endian)?);
+		}
+		let mut sampler_data = vec![];
+		read.take(data_size as u64).read_to_end(&mut sampler_data)?;
+		Ok(Smpl {
+			manufacturer: manufacturer,
+			product: product,
+			sample_period: sample_period,
+			midi_unity_note: midi_unity_note,
+			midi_pitch_fraction: midi_pitch_fraction,
+			smpte_format: smpte_format,
+			smpte_offset: smpte_offset,
+			loops: loops,
+			sampler_data: sampler_data,
+		})
+	}
+}
+
+impl Ser for Smpl {
+	fn ser<W: Write>(&self, write: &mut W) -> io::Result<()> {
+		if self.loops.len() > u32::max_value() as usize {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Too many sample loops: {}", self.loops.len())));
+		}
+		if self.sampler_data.len() > u32::max_value() as usize {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Too much sampler data: {} bytes", self.sampler_data.len())));
+		}
+		write.write_u32::<LittleEndian>(self.manufacturer)?;
+		write.write_u32::<LittleEndian>(self.product)?;
+		write.write_u32::<LittleEndian>(self.sample_period)?;
+		write.write_u32::<LittleEndian>(self.midi_unity_note)?;
+		write.write_u32::<LittleEndian>(self.midi_pitch_fraction)?;
+		write.write_u32::<LittleEndian>(self.smpte_format)?;
+		write.write_u32::<LittleEndian>(self.smpte_offset)?;
+		write.write_u32::<LittleEndian>(self.loops.len() as u32)?;
+		write.write_u32::<LittleEndian>(self.sampler_data.len() as u32)?;
+		self.loops.ser(write)?;
+		write.write_all(&self.sampler_data)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use fourcc::FourCC;
+	use riff::{Riff, RiffWriter};
+	use super::*;
+
+	#[test]
+	fn bext_layout() {
+		let bext = Bext {
+			description: "take 1".to_string(),
+			origination_date: "2017-05-01".to_string(),
+			origination_time: "12:30:00".to_string(),
+			time_reference: 0x0102030405060708,
+			version: 2,
+			loudness_value: -2300,
+			coding_history: "A=PCM,F=48000".to_string(),
+			..Bext::default()
+		};
+		let mut data = vec![];
+		bext.ser(&mut data).unwrap();
+		assert_eq!(data.len(), 602 + 13);
+		assert_eq!(&data[..7], b"take 1\0");
+		assert_eq!(&data[320..338], b"2017-05-0112:30:00");
+		assert_eq!(&data[338..346], &[8, 7, 6, 5, 4, 3, 2, 1]);
+		assert_eq!(Bext::deser(&mut &data[..]).unwrap(), bext);
+	}
+
+	#[test]
+	fn cue_points() {
+		let data = b"\x01\0\0\0\
+		             \x05\0\0\0\x10\0\0\0data\0\0\0\0\0\0\0\0\x10\0\0\0";
+		let cue = Cue::deser(&mut &data[..]).unwrap();
+		assert_eq!(cue.points, vec![CuePoint {
+			id: 5,
+			position: 16,
+			chunk: FourCC::from_str("data").unwrap(),
+			chunk_start: 0,
+			block_start: 0,
+			sample_offset: 16,
+		}]);
+		let mut written = vec![];
+		cue.ser(&mut written).unwrap();
+		assert_eq!(written, &data[..]);
+	}
+
+	#[test]
+	fn smpl_round_trip() {
+		let smpl = Smpl {
+			sample_period: 22675,
+			midi_unity_note: 60,
+			loops: vec![SampleLoop { cue_id: 1, kind: 0, start: 100, end: 199, fraction: 0, play_count: 0 }],
+			sampler_data: vec![1, 2, 3],
+			..Smpl::default()
+		};
+		let mut data = vec![];
+		smpl.ser(&mut data).unwrap();
+		assert_eq!(data.len(), 36 + 24 + 3);
+		assert_eq!(Smpl::deser(&mut &data[..]).unwrap(), smpl);
+	}
+
+	#[test]
+	fn adtl_list() {
+		let adtl = Adtl {
+			entries: vec![
+				AdtlEntry::Label { cue_id: 1, text: "intro".to_string() },
+				AdtlEntry::Note { cue_id: 1, text: "quiet".to_string() },
+				AdtlEntry::LabeledText(LabeledText {
+					cue_id: 2,
+					sample_length: 480,
+					purpose: FourCC::from_str("rgn ").unwrap(),
+					country: 0,
+					language: 0,
+					dialect: 0,
+					code_page: 0,
+					text: "verse".to_string(),
+				}),
+			],
+		};
+		let mut writer = RiffWriter::new(Cursor::new(vec![]));
+		writer.begin_riff(FourCC::from_str("WAVE").unwrap()).unwrap();
+		adtl.write(&mut writer).unwrap();
+		writer.end().unwrap();
+		let data = writer.finish().unwrap().into_inner();
+
+		let mut riff = Riff::new(Cursor::new(data)).unwrap();
+		let mut form = riff.iter().next().unwrap().unwrap();
+		let mut list = form.iter().next().unwrap().unwrap().list_or(()).unwrap();
+		let read = Adtl::from_list(&mut list).unwrap();
+		assert_eq!(read, adtl);
+		assert_eq!((read.label(1), read.note(1), read.label(2)), (Some("intro"), Some("quiet"), None));
+	}
+}
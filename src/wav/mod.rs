@@ -0,0 +1,287 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::fmt;
+use std::time::Duration;
+
+use data::*;
+use riff;
+use deser::Deser;
+use fourcc::FourCC;
+
+mod meta;
+
+pub use data::{WaveFormat, WAVEFORMATEX};
+pub use self::meta::{Bext, IXml, Cue, CuePoint, Adtl, AdtlEntry, LabeledText, Smpl, SampleLoop};
+pub use self::meta::{FCC_BEXT, FCC_IXML, FCC_CUE, FCC_ADTL, FCC_LABL, FCC_NOTE, FCC_LTXT, FCC_SMPL};
+
+/// Size of `fmt ` chunk written for plain PCM, without `cbSize` field.
+const PCM_FORMAT_SIZE: usize = 16;
+
+fn format_error(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Parsed `RIFF WAVE` file.
+#[derive(Debug)]
+pub struct Wav<'a, T: 'a + Read + Seek + fmt::Debug> {
+	format: WaveFormat,
+	fact: Option<u64>,
+	data: riff::Chunk<'a, T>,
+	nodes: Vec<riff::Node<'a, T>>,
+}
+
+impl<'a, T: 'a + Read + Seek + fmt::Debug> Wav<'a, T> {
+	pub fn from_riff(riff: &'a mut riff::Riff<T>) -> io::Result<Self> {
+		let mut form = riff.iter().next().ok_or_else(|| format_error("No RIFF form"))??;
+		if form.fourcc() != FCC_WAVE {
+			return Err(format_error("Not a WAVE form"));
+		}
+		let sample_count = form.ds64().map(|ds64| ds64.sample_count);
+		let mut format = None;
+		let mut fact = None;
+		let mut data = None;
+		let mut nodes = vec![];
+		for item in form.iter() {
+			let node = item?;
+			match node.fourcc() {
+				FCC_FMT => {
+					if format.is_some() {
+						return Err(format_error("Duplicate fmt chunk"));
+					}
+					format = Some(read_format(&mut node.chunk_or(format_error("fmt must be a chunk"))?)?);
+				}
+				FCC_FACT => {
+					let mut chunk = node.chunk_or(format_error("fact must be a chunk"))?;
+					let value = chunk.endian().read_u32(&mut chunk.read())?;
+					fact = match sample_count {
+						Some(count) if value == riff::SIZE_PLACEHOLDER => Some(count),
+						_ => Some(value as u64),
+					};
+				}
+				FCC_DATA => {
+					if data.is_some() {
+						return Err(format_error("Duplicate data chunk"));
+					}
+					data = Some(node.chunk_or(format_error("data must be a chunk"))?);
+				}
+				_ => nodes.push(node),
+			}
+		}
+		let format: WaveFormat = format.ok_or_else(|| format_error("No fmt chunk"))?;
+		if format.header().block_align == 0 {
+			return Err(format_error("Zero block align"));
+		}
+		Ok(Wav {
+			format: format,
+			fact: fact,
+			data: data.ok_or_else(|| format_error("No data chunk"))?,
+			nodes: nodes,
+		})
+	}
+
+	pub fn format(&self) -> &WaveFormat {
+		&self.format
+	}
+
+	/// Sample frames per channel from `fact` chunk, if present.
+	pub fn fact(&self) -> Option<u64> {
+		self.fact
+	}
+
+	/// Sample frames per channel. Taken from `fact` if present, computed from `data` size otherwise.
+	pub fn sample_count(&self) -> u64 {
+		match self.fact {
+			Some(count) => count,
+			None => self.data.size() / self.format.header().block_align as u64,
+		}
+	}
+
+	pub fn duration(&self) -> Duration {
+		let rate = self.format.header().samples_per_sec as u64;
+		if rate == 0 {
+			return Duration::from_secs(0);
+		}
+		let count = self.sample_count();
+		Duration::new(count / rate, ((count % rate) * 1_000_000_000 / rate) as u32)
+	}
+
+	/// Size of `data` payload in bytes.
+	pub fn data_size(&self) -> u64 {
+		self.data.size()
+	}
+
+	/// Reader of raw sample data, positioned at first frame.
+	pub fn samples<'b>(&'b mut self) -> SampleReader<'a, 'b, T> {
+		SampleReader {
+			block_align: self.format.header().block_align as u64,
+			inner: self.data.read(),
+		}
+	}
+
+	/// Chunks and lists other than `fmt `, `fact` and `data`.
+	pub fn nodes(&self) -> &[riff::Node<'a, T>] {
+		&self.nodes
+	}
+
+	pub fn nodes_mut(&mut self) -> &mut [riff::Node<'a, T>] {
+		&mut self.nodes
+	}
+
+	fn chunk(&mut self, fcc: FourCC) -> Option<&mut riff::Chunk<'a, T>> {
+		self.nodes.iter_mut().filter_map(|node| match *node {
+			riff::Node::Chunk(ref mut chunk) if chunk.fourcc() == fcc => Some(chunk),
+			_ => None,
+		}).next()
+	}
+
+	pub fn bext(&mut self) -> io::Result<Option<Bext>> {
+		self.chunk(FCC_BEXT).map(|chunk| read_chunk(chunk)).map_or(Ok(None), |bext| bext.map(Some))
+	}
+
+	pub fn ixml(&mut self) -> io::Result<Option<IXml>> {
+		self.chunk(FCC_IXML).map(|chunk| read_chunk(chunk)).map_or(Ok(None), |ixml| ixml.map(Some))
+	}
+
+	pub fn cue(&mut self) -> io::Result<Option<Cue>> {
+		self.chunk(FCC_CUE).map(|chunk| read_chunk(chunk)).map_or(Ok(None), |cue| cue.map(Some))
+	}
+
+	pub fn smpl(&mut self) -> io::Result<Option<Smpl>> {
+		self.chunk(FCC_SMPL).map(|chunk| read_chunk(chunk)).map_or(Ok(None), |smpl| smpl.map(Some))
+	}
+
+	/// Labels and notes from `LIST adtl`.
+	pub fn adtl(&mut self) -> io::Result<Option<Adtl>> {
+		for node in &mut self.nodes {
+			if let riff::Node::List(ref mut list) = *node {
+				if list.fourcc() == FCC_ADTL {
+					return Adtl::from_list(list).map(Some);
+				}
+			}
+		}
+		Ok(None)
+	}
+}
+
+/// Reads record from chunk payload in byte order of the file.
+fn read_chunk<'a, T: 'a + Read + Seek + fmt::Debug, D: Deser>(chunk: &mut riff::Chunk<'a, T>) -> io::Result<D> {
+	let endian = chunk.endian();
+	D::deser_endian(&mut chunk.read(), endian)
+}
+
+/// Reads `fmt ` chunk. PCM chunks of 16 bytes lack `cbSize` and are read as if it was zero.
+pub(crate) fn read_format<'a, T: 'a + Read + Seek + fmt::Debug>(chunk: &mut riff::Chunk<'a, T>) -> io::Result<WaveFormat> {
+	let mut bytes = vec![];
+	chunk.read().read_to_end(&mut bytes)?;
+	if bytes.len() == PCM_FORMAT_SIZE {
+		bytes.extend_from_slice(&[0, 0]);
+	}
+	WaveFormat::deser_endian(&mut &bytes[..], chunk.endian())
+}
+
+/// Reader over `data` chunk payload.
+#[derive(Debug)]
+pub struct SampleReader<'a, 'b, T: 'a + Read + Seek + fmt::Debug> where 'a: 'b {
+	inner: riff::ChunkReader<'a, 'b, T>,
+	block_align: u64,
+}
+
+impl<'a, 'b, T: 'a + Read + Seek + fmt::Debug> SampleReader<'a, 'b, T> where 'a: 'b {
+	/// Move to start of sample frame `frame`.
+	pub fn seek_frame(&mut self, frame: u64) -> io::Result<()> {
+		self.inner.seek(SeekFrom::Start(frame * self.block_align)).map(|_| ())
+	}
+
+	/// Index of frame at current position.
+	pub fn frame(&mut self) -> io::Result<u64> {
+		Ok(self.inner.seek(SeekFrom::Current(0))? / self.block_align)
+	}
+
+	/// Bytes per sample frame.
+	pub fn block_align(&self) -> u64 {
+		self.block_align
+	}
+}
+
+impl<'a, 'b, T: 'a + Read + Seek + fmt::Debug> Read for SampleReader<'a, 'b, T> where 'a: 'b {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.inner.read(buf)
+	}
+}
+
+impl<'a, 'b, T: 'a + Read + Seek + fmt::Debug> Seek for SampleReader<'a, 'b, T> where 'a: 'b {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		self.inner.seek(pos)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Cursor, Read};
+	use std::time::Duration;
+
+	use fourcc::FourCC;
+	use riff::{Riff, RiffWriter};
+	use super::{Wav, FCC_WAVE, FCC_FMT, FCC_FACT, FCC_DATA};
+
+	/// Stereo 16-bit PCM at 8000 Hz with optional `fact`.
+	fn file(fact: Option<u32>, frames: usize) -> Vec<u8> {
+		let mut writer = RiffWriter::new(Cursor::new(vec![]));
+		writer.begin_riff(FCC_WAVE).unwrap();
+		writer.write_chunk(FCC_FMT, b"\x01\0\x02\0\x40\x1f\0\0\0\x7d\0\0\x04\0\x10\0").unwrap();
+		if let Some(fact) = fact {
+			writer.write_chunk(FCC_FACT, &[fact as u8, (fact >> 8) as u8, (fact >> 16) as u8, (fact >> 24) as u8]).unwrap();
+		}
+		writer.write_chunk(FourCC::from_str("junk").unwrap(), b"x").unwrap();
+		let samples = (0..frames * 4).map(|i| i as u8).collect::<Vec<_>>();
+		writer.write_chunk(FCC_DATA, &samples).unwrap();
+		writer.end().unwrap();
+		writer.finish().unwrap().into_inner()
+	}
+
+	#[test]
+	fn pcm_file() {
+		let mut riff = Riff::new(Cursor::new(file(None, 8000 + 2000))).unwrap();
+		let mut wav = Wav::from_riff(&mut riff).unwrap();
+		let header = *wav.format().header();
+		assert_eq!({ header.format_tag }, 1);
+		assert_eq!({ header.channels }, 2);
+		assert_eq!({ header.samples_per_sec }, 8000);
+		assert_eq!({ header.block_align }, 4);
+		assert_eq!({ header.size }, 0);
+		assert_eq!(wav.fact(), None);
+		assert_eq!(wav.data_size(), 40000);
+		assert_eq!(wav.sample_count(), 10000);
+		assert_eq!(wav.duration(), Duration::from_millis(1250));
+		assert_eq!(wav.nodes().len(), 1);
+		let mut samples = wav.samples();
+		samples.seek_frame(3).unwrap();
+		let mut frame = [0; 4];
+		samples.read_exact(&mut frame).unwrap();
+		assert_eq!(frame, [12, 13, 14, 15]);
+		assert_eq!(samples.frame().unwrap(), 4);
+	}
+
+	#[test]
+	fn fact_overrides_data_size() {
+		let mut riff = Riff::new(Cursor::new(file(Some(3), 4))).unwrap();
+		let wav = Wav::from_riff(&mut riff).unwrap();
+		assert_eq!((wav.fact(), wav.sample_count()), (Some(3), 3));
+	}
+
+	#[test]
+	fn rifx_file() {
+		let data = b"RIFX\0\0\0\x4cWAVE\
+		             fmt \0\0\0\x10\0\x01\0\x01\0\0\x1f\x40\0\0\x3e\x80\0\x02\0\x10\
+		             cue \0\0\0\x1c\0\0\0\x01\0\0\0\x05\0\0\x01\0data\0\0\0\0\0\0\0\0\0\0\0\x10\
+		             data\0\0\0\x04\x01\x02\x03\x04".to_vec();
+		let mut riff = Riff::new(Cursor::new(data)).unwrap();
+		let mut wav = Wav::from_riff(&mut riff).unwrap();
+		let header = *wav.format().header();
+		assert_eq!(({ header.format_tag }, { header.channels }, { header.samples_per_sec }), (1, 1, 8000));
+		assert_eq!(({ header.avg_bytes_per_sec }, { header.block_align }, { header.bits_per_sample }), (16000, 2, 16));
+		assert_eq!(wav.sample_count(), 2);
+		let cue = wav.cue().unwrap().unwrap();
+		assert_eq!((cue.points[0].id, cue.points[0].position, cue.points[0].sample_offset), (5, 0x100, 16));
+		assert_eq!(wav.smpl().unwrap(), None);
+	}
+}
@@ -0,0 +1,560 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::fmt;
+use std::time::Duration;
+
+use byteorder::{ReadBytesExt, LittleEndian};
+
+use riff;
+use fourcc::FourCC;
+
+pub const FCC_WEBP: FourCC = FourCC([b'W', b'E', b'B', b'P']);
+pub const FCC_VP8:  FourCC = FourCC([b'V', b'P', b'8', b' ']);
+pub const FCC_VP8L: FourCC = FourCC([b'V', b'P', b'8', b'L']);
+pub const FCC_VP8X: FourCC = FourCC([b'V', b'P', b'8', b'X']);
+pub const FCC_ALPH: FourCC = FourCC([b'A', b'L', b'P', b'H']);
+pub const FCC_ANIM: FourCC = FourCC([b'A', b'N', b'I', b'M']);
+pub const FCC_ANMF: FourCC = FourCC([b'A', b'N', b'M', b'F']);
+pub const FCC_ICCP: FourCC = FourCC([b'I', b'C', b'C', b'P']);
+pub const FCC_EXIF: FourCC = FourCC([b'E', b'X', b'I', b'F']);
+pub const FCC_XMP:  FourCC = FourCC([b'X', b'M', b'P', b' ']);
+
+/// Signature byte starting `VP8L` bitstream.
+const VP8L_SIGNATURE: u8 = 0x2f;
+/// Start code following `VP8 ` key frame tag.
+const VP8_START_CODE: [u8; 3] = [0x9d, 0x01, 0x2a];
+
+fn format_error(message: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_fourcc<R: Read>(read: &mut R) -> io::Result<FourCC> {
+	let mut fcc = [0; 4];
+	read.read_exact(&mut fcc)?;
+	Ok(FourCC(fcc))
+}
+
+/// Feature flags of `VP8X` chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vp8xFlag {
+	Animation = 0x02,
+	Xmp = 0x04,
+	Exif = 0x08,
+	Alpha = 0x10,
+	Icc = 0x20,
+}
+
+/// `VP8X` extended format header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Vp8xHeader {
+	pub flags: u8,
+	pub canvas_width: u32,
+	pub canvas_height: u32,
+}
+
+impl Vp8xHeader {
+	pub fn read<R: Read>(read: &mut R) -> io::Result<Self> {
+		let flags = read.read_u8()?;
+		let mut reserved = [0; 3];
+		read.read_exact(&mut reserved)?;
+		Ok(Vp8xHeader {
+			flags: flags,
+			canvas_width: read.read_u24::<LittleEndian>()? + 1,
+			canvas_height: read.read_u24::<LittleEndian>()? + 1,
+		})
+	}
+
+	pub fn has(&self, flag: Vp8xFlag) -> bool {
+		self.flags & flag as u8 != 0
+	}
+}
+
+/// Frame header of lossy `VP8 ` bitstream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Vp8Header {
+	pub version: u8,
+	pub show_frame: bool,
+	pub partition_size: u32,
+	pub width: u16,
+	pub height: u16,
+	pub horizontal_scale: u8,
+	pub vertical_scale: u8,
+}
+
+impl Vp8Header {
+	pub fn read<R: Read>(read: &mut R) -> io::Result<Self> {
+		let tag = read.read_u24::<LittleEndian>()?;
+		if tag & 1 != 0 {
+			return Err(format_error("VP8 bitstream doesn't start with key frame".to_string()));
+		}
+		let mut start_code = [0; 3];
+		read.read_exact(&mut start_code)?;
+		if start_code != VP8_START_CODE {
+			return Err(format_error(format!("Bad VP8 start code {:?}", start_code)));
+		}
+		let width = read.read_u16::<LittleEndian>()?;
+		let height = read.read_u16::<LittleEndian>()?;
+		Ok(Vp8Header {
+			version: ((tag >> 1) & 7) as u8,
+			show_frame: (tag >> 4) & 1 != 0,
+			partition_size: tag >> 5,
+			width: width & 0x3fff,
+			height: height & 0x3fff,
+			horizontal_scale: (width >> 14) as u8,
+			vertical_scale: (height >> 14) as u8,
+		})
+	}
+}
+
+/// Header of lossless `VP8L` bitstream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Vp8lHeader {
+	pub width: u16,
+	pub height: u16,
+	pub alpha_is_used: bool,
+	pub version: u8,
+}
+
+impl Vp8lHeader {
+	pub fn read<R: Read>(read: &mut R) -> io::Result<Self> {
+		let signature = read.read_u8()?;
+		if signature != VP8L_SIGNATURE {
+			return Err(format_error(format!("Bad VP8L signature {:#x}", signature)));
+		}
+		let bits = read.read_u32::<LittleEndian>()?;
+		Ok(Vp8lHeader {
+			width: (bits & 0x3fff) as u16 + 1,
+			height: ((bits >> 14) & 0x3fff) as u16 + 1,
+			alpha_is_used: (bits >> 28) & 1 != 0,
+			version: (bits >> 29) as u8,
+		})
+	}
+}
+
+/// Header of `ALPH` chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlphHeader {
+	/// 0 - raw, 1 - lossless compressed.
+	pub compression: u8,
+	/// 0 - none, 1 - horizontal, 2 - vertical, 3 - gradient.
+	pub filtering: u8,
+	pub preprocessing: u8,
+}
+
+impl AlphHeader {
+	pub fn read<R: Read>(read: &mut R) -> io::Result<Self> {
+		let flags = read.read_u8()?;
+		Ok(AlphHeader {
+			compression: flags & 3,
+			filtering: (flags >> 2) & 3,
+			preprocessing: (flags >> 4) & 3,
+		})
+	}
+}
+
+/// `ANIM` chunk: global animation parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnimHeader {
+	/// Stored in `[Blue, Green, Red, Alpha]` byte order.
+	pub background_color: [u8; 4],
+	/// 0 means infinite.
+	pub loop_count: u16,
+}
+
+impl AnimHeader {
+	pub fn read<R: Read>(read: &mut R) -> io::Result<Self> {
+		let mut background_color = [0; 4];
+		read.read_exact(&mut background_color)?;
+		Ok(AnimHeader {
+			background_color: background_color,
+			loop_count: read.read_u16::<LittleEndian>()?,
+		})
+	}
+}
+
+/// Header of `ANMF` chunk, preceding frame data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnmfHeader {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+	/// Milliseconds.
+	pub duration: u32,
+	/// Frame is drawn over canvas instead of alpha-blending.
+	pub no_blend: bool,
+	/// Frame area is cleared to background before next frame.
+	pub dispose: bool,
+}
+
+impl AnmfHeader {
+	pub fn read<R: Read>(read: &mut R) -> io::Result<Self> {
+		let x = read.read_u24::<LittleEndian>()?;
+		let y = read.read_u24::<LittleEndian>()?;
+		let width = read.read_u24::<LittleEndian>()?;
+		let height = read.read_u24::<LittleEndian>()?;
+		let duration = read.read_u24::<LittleEndian>()?;
+		let flags = read.read_u8()?;
+		Ok(AnmfHeader {
+			x: x * 2,
+			y: y * 2,
+			width: width + 1,
+			height: height + 1,
+			duration: duration,
+			no_blend: flags & 2 != 0,
+			dispose: flags & 1 != 0,
+		})
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bitstream {
+	Lossy(Vp8Header),
+	Lossless(Vp8lHeader),
+}
+
+/// Location of data inside one of chunks kept by `WebP`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Span {
+	chunk: usize,
+	pos: u64,
+	size: u64,
+}
+
+/// Single image of still or animated file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+	/// Placement on canvas. Still images cover whole canvas with zero duration.
+	pub header: AnmfHeader,
+	pub bitstream: Bitstream,
+	pub alpha: Option<AlphHeader>,
+	/// Absolute offset of `VP8 `/`VP8L` payload.
+	pub offset: u64,
+	pub size: u64,
+	data: Span,
+	alpha_data: Option<Span>,
+}
+
+/// Parsed `RIFF WEBP` file.
+#[derive(Debug)]
+pub struct WebP<'a, T: 'a + Read + Seek + fmt::Debug> {
+	vp8x: Option<Vp8xHeader>,
+	anim: Option<AnimHeader>,
+	frames: Vec<Frame>,
+	iccp: Option<usize>,
+	exif: Option<usize>,
+	xmp: Option<usize>,
+	chunks: Vec<riff::Chunk<'a, T>>,
+}
+
+impl<'a, T: 'a + Read + Seek + fmt::Debug> WebP<'a, T> {
+	pub fn from_riff(riff: &'a mut riff::Riff<T>) -> io::Result<Self> {
+		let mut form = riff.iter().next().ok_or_else(|| format_error("No RIFF form".to_string()))??;
+		if form.fourcc() != FCC_WEBP {
+			return Err(format_error(format!("Expected WEBP form, found {}", form.fourcc())));
+		}
+		let mut webp = WebP {
+			vp8x: None,
+			anim: None,
+			frames: vec![],
+			iccp: None,
+			exif: None,
+			xmp: None,
+			chunks: vec![],
+		};
+		let mut alpha = None;
+		for item in form.iter() {
+			let mut chunk = match item? {
+				riff::Node::Chunk(chunk) => chunk,
+				riff::Node::List(list) => return Err(format_error(format!("Unexpected LIST {} in WEBP", list.fourcc()))),
+			};
+			let index = webp.chunks.len();
+			match chunk.fourcc() {
+				FCC_VP8X => webp.vp8x = Some(Vp8xHeader::read(&mut chunk.read())?),
+				FCC_ANIM => webp.anim = Some(AnimHeader::read(&mut chunk.read())?),
+				FCC_ICCP => webp.iccp = Some(index),
+				FCC_EXIF => webp.exif = Some(index),
+				FCC_XMP => webp.xmp = Some(index),
+				FCC_ALPH => {
+					let header = AlphHeader::read(&mut chunk.read())?;
+					alpha = Some((header, Span { chunk: index, pos: 1, size: chunk.size().saturating_sub(1) }));
+				}
+				FCC_VP8 | FCC_VP8L => {
+					let bitstream = read_bitstream(chunk.fourcc(), &mut chunk.read())?;
+					let (width, height) = bitstream_size(&bitstream);
+					webp.frames.push(Frame {
+						header: AnmfHeader { x: 0, y: 0, width: width, height: height, duration: 0, no_blend: false, dispose: false },
+						bitstream: bitstream,
+						alpha: alpha.map(|(header, _)| header),
+						offset: chunk.data_offset(),
+						size: chunk.size(),
+						data: Span { chunk: index, pos: 0, size: chunk.size() },
+						alpha_data: alpha.take().map(|(_, span)| span),
+					});
+				}
+				FCC_ANMF => {
+					let frame = read_anmf(&mut chunk, index)?;
+					webp.frames.push(frame);
+				}
+				_ => {}
+			}
+			webp.chunks.push(chunk);
+		}
+		if webp.frames.is_empty() {
+			return Err(format_error("No image data in WEBP".to_string()));
+		}
+		Ok(webp)
+	}
+
+	pub fn vp8x(&self) -> Option<&Vp8xHeader> {
+		self.vp8x.as_ref()
+	}
+
+	pub fn anim(&self) -> Option<&AnimHeader> {
+		self.anim.as_ref()
+	}
+
+	/// Canvas size from `VP8X`, or size of the only image for simple files.
+	pub fn canvas_size(&self) -> (u32, u32) {
+		match self.vp8x {
+			Some(vp8x) => (vp8x.canvas_width, vp8x.canvas_height),
+			None => (self.frames[0].header.width, self.frames[0].header.height),
+		}
+	}
+
+	pub fn is_animated(&self) -> bool {
+		self.vp8x.map_or(false, |vp8x| vp8x.has(Vp8xFlag::Animation))
+	}
+
+	pub fn frame_count(&self) -> usize {
+		self.frames.len()
+	}
+
+	pub fn frames(&self) -> &[Frame] {
+		&self.frames
+	}
+
+	/// Sum of frame durations.
+	pub fn duration(&self) -> Duration {
+		Duration::from_millis(self.frames.iter().map(|frame| frame.header.duration as u64).sum())
+	}
+
+	/// `VP8 `/`VP8L` payload of frame `index`.
+	pub fn frame_data(&mut self, index: usize) -> io::Result<Vec<u8>> {
+		let span = self.frames[index].data;
+		self.read_span(span)
+	}
+
+	/// `ALPH` payload of frame `index` after its header byte.
+	pub fn frame_alpha(&mut self, index: usize) -> io::Result<Option<Vec<u8>>> {
+		match self.frames[index].alpha_data {
+			Some(span) => self.read_span(span).map(Some),
+			None => Ok(None),
+		}
+	}
+
+	pub fn iccp(&mut self) -> io::Result<Option<Vec<u8>>> {
+		let index = self.iccp;
+		self.read_chunk(index)
+	}
+
+	pub fn exif(&mut self) -> io::Result<Option<Vec<u8>>> {
+		let index = self.exif;
+		self.read_chunk(index)
+	}
+
+	pub fn xmp(&mut self) -> io::Result<Option<Vec<u8>>> {
+		let index = self.xmp;
+		self.read_chunk(index)
+	}
+
+	fn read_chunk(&mut self, index: Option<usize>) -> io::Result<Option<Vec<u8>>> {
+		match index {
+			Some(index) => {
+				let size = self.chunks[index].size();
+				self.read_span(Span { chunk: index, pos: 0, size: size }).map(Some)
+			}
+			None => Ok(None),
+		}
+	}
+
+	fn read_span(&mut self, span: Span) -> io::Result<Vec<u8>> {
+		let mut read = self.chunks[span.chunk].read();
+		read.seek(SeekFrom::Start(span.pos))?;
+		let mut data = vec![];
+		read.take(span.size).read_to_end(&mut data)?;
+		Ok(data)
+	}
+}
+
+fn read_bitstream<R: Read>(fcc: FourCC, read: &mut R) -> io::Result<Bitstream> {
+	if fcc == FCC_VP8L {
+		Vp8lHeader::read(read).map(Bitstream::Lossless)
+	} else {
+		Vp8Header::read(read).map(Bitstream::Lossy)
+	}
+}
+
+fn bitstream_size(bitstream: &Bitstream) -> (u32, u32) {
+	match *bitstream {
+		Bitstream::Lossy(ref vp8) => (vp8.width as u32, vp8.height as u32),
+		Bitstream::Lossless(ref vp8l) => (vp8l.width as u32, vp8l.height as u32),
+	}
+}
+
+/// Reads `ANMF` header and locates frame data among its sub-chunks.
+fn read_anmf<'a, T: 'a + Read + Seek + fmt::Debug>(chunk: &mut riff::Chunk<'a, T>, index: usize) -> io::Result<Frame> {
+	let data_offset = chunk.data_offset();
+	let end = chunk.size();
+	let mut read = chunk.read();
+	let header = AnmfHeader::read(&mut read)?;
+	let mut pos = read.seek(SeekFrom::Current(0))?;
+	let mut alpha = None;
+	while pos + 8 <= end {
+		let fcc = read_fourcc(&mut read)?;
+		let size = read.read_u32::<LittleEndian>()? as u64;
+		let start = pos + 8;
+		if start + size > end {
+			return Err(format_error(format!("{} in ANMF is too big: {}", fcc, size)));
+		}
+		match fcc {
+			FCC_ALPH => {
+				let alph = AlphHeader::read(&mut read)?;
+				alpha = Some((alph, Span { chunk: index, pos: start + 1, size: size.saturating_sub(1) }));
+			}
+			FCC_VP8 | FCC_VP8L => {
+				let bitstream = read_bitstream(fcc, &mut read)?;
+				return Ok(Frame {
+					header: header,
+					bitstream: bitstream,
+					alpha: alpha.map(|(alph, _)| alph),
+					offset: data_offset + start,
+					size: size,
+					data: Span { chunk: index, pos: start, size: size },
+					alpha_data: alpha.map(|(_, span)| span),
+				});
+			}
+			_ => {}
+		}
+		pos = start + size + size % 2;
+		read.seek(SeekFrom::Start(pos))?;
+	}
+	Err(format_error("ANMF without image data".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+	use std::time::Duration;
+
+	use fourcc::FourCC;
+	use riff::{Riff, RiffWriter};
+	use super::*;
+
+	fn u24(value: u32) -> [u8; 3] {
+		[value as u8, (value >> 8) as u8, (value >> 16) as u8]
+	}
+
+	/// `VP8L` payload of `width` x `height` image.
+	fn vp8l(width: u32, height: u32) -> Vec<u8> {
+		let bits = (width - 1) | (height - 1) << 14 | 1 << 28;
+		vec![VP8L_SIGNATURE, bits as u8, (bits >> 8) as u8, (bits >> 16) as u8, (bits >> 24) as u8, 0xaa]
+	}
+
+	/// Chunk with header and padding, as nested in `ANMF`.
+	fn sub_chunk(fcc: FourCC, payload: &[u8]) -> Vec<u8> {
+		let mut data = fcc.0.to_vec();
+		data.extend_from_slice(&[payload.len() as u8, 0, 0, 0]);
+		data.extend_from_slice(payload);
+		if payload.len() % 2 == 1 {
+			data.push(0);
+		}
+		data
+	}
+
+	fn file(chunks: &[(FourCC, Vec<u8>)]) -> Vec<u8> {
+		let mut writer = RiffWriter::new(Cursor::new(vec![]));
+		writer.begin_riff(FCC_WEBP).unwrap();
+		for &(fcc, ref payload) in chunks {
+			writer.write_chunk(fcc, payload).unwrap();
+		}
+		writer.end().unwrap();
+		writer.finish().unwrap().into_inner()
+	}
+
+	#[test]
+	fn simple_lossy() {
+		// Key frame, version 0, shown, partition size 8.
+		let tag = 1 << 4 | 8 << 5;
+		let mut vp8 = u24(tag).to_vec();
+		vp8.extend_from_slice(&VP8_START_CODE);
+		vp8.extend_from_slice(&[0x40, 0x01, 0xf0, 0x40]);
+		let mut riff = Riff::new(Cursor::new(file(&[(FCC_VP8, vp8)]))).unwrap();
+		let webp = WebP::from_riff(&mut riff).unwrap();
+		assert_eq!(webp.canvas_size(), (320, 240));
+		assert!(!webp.is_animated());
+		match webp.frames()[0].bitstream {
+			Bitstream::Lossy(header) => {
+				assert_eq!((header.show_frame, header.partition_size), (true, 8));
+				assert_eq!((header.horizontal_scale, header.vertical_scale), (0, 1));
+			}
+			ref other => panic!("unexpected {:?}", other),
+		}
+		assert_eq!(webp.frames()[0].offset, 20);
+	}
+
+	#[test]
+	fn extended_animation() {
+		let mut vp8x = vec![Vp8xFlag::Animation as u8 | Vp8xFlag::Alpha as u8, 0, 0, 0];
+		vp8x.extend_from_slice(&u24(19));
+		vp8x.extend_from_slice(&u24(9));
+		let anim = vec![1, 2, 3, 4, 0, 0];
+		let mut frames = vec![];
+		for &(x, duration, alpha) in &[(0, 100, false), (4, 250, true)] {
+			let mut anmf = vec![];
+			anmf.extend_from_slice(&u24(x / 2));
+			anmf.extend_from_slice(&u24(0));
+			anmf.extend_from_slice(&u24(9));
+			anmf.extend_from_slice(&u24(4));
+			anmf.extend_from_slice(&u24(duration));
+			anmf.push(2);
+			if alpha {
+				anmf.extend_from_slice(&sub_chunk(FCC_ALPH, &[0x01, 7, 8, 9]));
+			}
+			anmf.extend_from_slice(&sub_chunk(FCC_VP8L, &vp8l(10, 5)));
+			frames.push((FCC_ANMF, anmf));
+		}
+		let mut chunks = vec![(FCC_VP8X, vp8x), (FCC_ICCP, b"icc".to_vec()), (FCC_ANIM, anim)];
+		chunks.extend(frames);
+		chunks.push((FCC_XMP, b"<x/>".to_vec()));
+		let mut riff = Riff::new(Cursor::new(file(&chunks))).unwrap();
+		let mut webp = WebP::from_riff(&mut riff).unwrap();
+
+		assert_eq!(webp.canvas_size(), (20, 10));
+		assert!(webp.is_animated());
+		assert!(webp.vp8x().unwrap().has(Vp8xFlag::Alpha));
+		assert!(!webp.vp8x().unwrap().has(Vp8xFlag::Exif));
+		assert_eq!(webp.anim().unwrap().background_color, [1, 2, 3, 4]);
+		assert_eq!(webp.frame_count(), 2);
+		assert_eq!(webp.duration(), Duration::from_millis(350));
+		let second = webp.frames()[1].clone();
+		assert_eq!((second.header.x, second.header.width, second.header.height), (4, 10, 5));
+		assert!(second.header.no_blend);
+		assert_eq!(second.alpha, Some(AlphHeader { compression: 1, filtering: 0, preprocessing: 0 }));
+		match second.bitstream {
+			Bitstream::Lossless(header) => assert_eq!((header.width, header.height, header.alpha_is_used), (10, 5, true)),
+			ref other => panic!("unexpected {:?}", other),
+		}
+		assert_eq!(webp.frame_data(1).unwrap(), vp8l(10, 5));
+		assert_eq!(webp.frame_alpha(1).unwrap(), Some(vec![7, 8, 9]));
+		assert_eq!(webp.frame_alpha(0).unwrap(), None);
+		assert_eq!(webp.iccp().unwrap(), Some(b"icc".to_vec()));
+		assert_eq!(webp.xmp().unwrap(), Some(b"<x/>".to_vec()));
+		assert_eq!(webp.exif().unwrap(), None);
+	}
+
+	#[test]
+	fn no_image() {
+		let mut riff = Riff::new(Cursor::new(file(&[(FCC_EXIF, b"x".to_vec())]))).unwrap();
+		assert!(WebP::from_riff(&mut riff).is_err());
+	}
+}